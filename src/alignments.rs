@@ -0,0 +1,318 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate csv;
+extern crate rust_htslib;
+
+use crate::error::{Error, Result};
+use crate::reference::ReferenceFasta;
+use clap::ValueEnum;
+use csv::ReaderBuilder;
+use rust_htslib::{bam, bam::HeaderView, bam::Read};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Indexed read alignments in BAM or CRAM format. Decoding CRAM requires the reference sequence
+/// against which the reads were aligned.
+#[derive(Clone, Debug)]
+pub struct AlignmentFile {
+    path: PathBuf,
+    reference: Option<PathBuf>,
+    pub aliases: ContigAliases,
+}
+
+/// Field of the `@RG` header lines by which the reads in multiplexed alignments are split into
+/// separate profiles.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SplitBy {
+    /// One profile per read group, named by its ID
+    ReadGroup,
+    /// One profile per sample, pooling all read groups with the same SM field
+    Sample,
+}
+
+/// Map of chromosome names used in allele definitions to the names used in alignment headers, for
+/// panels and alignments that follow different naming conventions (e.g. UCSC `chr1` vs Ensembl
+/// `1`).
+#[derive(Clone, Debug, Default)]
+pub struct ContigAliases {
+    aliases: HashMap<String, String>,
+}
+
+impl ContigAliases {
+    /// Load aliases from a CSV file with a header row and two columns: the chromosome name used in
+    /// the allele definitions and the corresponding name used in the alignments.
+    pub fn from_csv(csv_path: &Path) -> Result<ContigAliases> {
+        let mut reader = ReaderBuilder::new()
+            .from_path(csv_path)
+            .map_err(|e| Error::csv_file(csv_path, e))?;
+        let mut aliases = HashMap::new();
+        for result in reader.records() {
+            let record = result.map_err(|e| Error::csv_file(csv_path, e))?;
+            match (record.get(0), record.get(1)) {
+                (Some(panel), Some(alignments)) => {
+                    aliases.insert(panel.to_string(), alignments.to_string());
+                }
+                _ => return Err(Error::csv(csv_path, record.position(), "expected 2 fields")),
+            }
+        }
+        Ok(ContigAliases { aliases })
+    }
+
+    /// Name under which the given chromosome is expected in the alignments.
+    pub fn resolve<'a>(&'a self, chrom: &'a str) -> &'a str {
+        match self.aliases.get(chrom) {
+            Some(alias) => alias,
+            None => chrom,
+        }
+    }
+}
+
+impl AlignmentFile {
+    /// Alignments at the given path; a reference FASTA is required only for CRAM.
+    pub fn new(path: &Path, reference: Option<&PathBuf>) -> AlignmentFile {
+        AlignmentFile {
+            path: path.to_path_buf(),
+            reference: reference.cloned(),
+            aliases: ContigAliases::default(),
+        }
+    }
+
+    /// Header ID of the given chromosome, after applying any alias, or `None` if the chromosome
+    /// is not declared in the alignment header.
+    pub fn tid(&self, header: &HeaderView, chrom: &str) -> Option<u32> {
+        header.tid(self.aliases.resolve(chrom).as_bytes())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read group IDs declared in the `@RG` lines of the alignment header, grouped under the name
+    /// of the profile they contribute to: each read group on its own, or every read group of a
+    /// sample together. Fails if the header declares no read groups, or if splitting by sample and
+    /// a read group has no SM field.
+    pub fn read_groups(&self, split: SplitBy) -> Result<BTreeMap<String, HashSet<String>>> {
+        let mut groups: BTreeMap<String, HashSet<String>> = BTreeMap::new();
+        for (id, sample) in self.header_read_groups()? {
+            let name = match split {
+                SplitBy::ReadGroup => id.clone(),
+                SplitBy::Sample => sample.ok_or_else(|| {
+                    Error::Validation(format!(
+                        "read group {} in alignment header {} has no SM field",
+                        id,
+                        self.path.display()
+                    ))
+                })?,
+            };
+            groups.entry(name).or_default().insert(id);
+        }
+        if groups.is_empty() {
+            return Err(Error::Validation(format!(
+                "no read groups declared in alignment header {}",
+                self.path.display()
+            )));
+        }
+        Ok(groups)
+    }
+
+    /// Name of the sample whose reads are aligned: the SM field of the `@RG` lines in the alignment
+    /// header or, if no read group declares a sample, the file name without its extension. Fails
+    /// if the header declares more than one distinct sample.
+    pub fn sample_name(&self) -> Result<String> {
+        let samples: BTreeSet<String> = self
+            .header_read_groups()?
+            .into_iter()
+            .filter_map(|(_, sample)| sample)
+            .collect();
+        if samples.len() > 1 {
+            let samples: Vec<String> = samples.into_iter().collect();
+            return Err(Error::Validation(format!(
+                "alignment header {} declares multiple samples ({}); specify one with --name or \
+                 type each separately with --split-by sample",
+                self.path.display(),
+                samples.join(", ")
+            )));
+        }
+        match samples.into_iter().next() {
+            Some(sample) => Ok(sample),
+            None => {
+                let stem = self.path.file_stem().unwrap_or_default();
+                Ok(stem.to_string_lossy().to_string())
+            }
+        }
+    }
+
+    /// ID and SM field, if any, of each `@RG` line in the alignment header.
+    fn header_read_groups(&self) -> Result<Vec<(String, Option<String>)>> {
+        let reader = bam::Reader::from_path(&self.path)
+            .map_err(|e| Error::bam(&self.path, format!("could not open alignments: {}", e)))?;
+        let header = bam::Header::from_template(reader.header()).to_hashmap();
+        header
+            .get("RG")
+            .into_iter()
+            .flatten()
+            .map(|fields| match fields.get("ID") {
+                Some(id) => Ok((id.clone(), fields.get("SM").cloned())),
+                None => Err(Error::bam(
+                    &self.path,
+                    "@RG line without an ID field in alignment header",
+                )),
+            })
+            .collect()
+    }
+
+    /// Indicate whether the alignments are stored in CRAM format, based on the file extension.
+    pub fn is_cram(&self) -> bool {
+        match self.path.extension() {
+            Some(ext) => ext.eq_ignore_ascii_case("cram"),
+            None => false,
+        }
+    }
+
+    /// Open an indexed reader for the alignments. For CRAM input, the reference FASTA must be
+    /// provided and must declare every sequence in the CRAM header.
+    pub fn open(&self) -> Result<bam::IndexedReader> {
+        let mut reader = bam::IndexedReader::from_path(&self.path).map_err(|e| {
+            Error::bam(
+                &self.path,
+                format!("could not open indexed alignments: {}", e),
+            )
+        })?;
+        match &self.reference {
+            Some(fasta) => {
+                let reference = ReferenceFasta::from_path(fasta)?;
+                if self.is_cram() {
+                    reference.check_header(reader.header())?;
+                }
+                reader
+                    .set_reference(fasta)
+                    .map_err(|e| Error::bam(&self.path, e))?;
+            }
+            None if self.is_cram() => {
+                return Err(Error::Validation(format!(
+                    "a reference FASTA (--reference) is required to decode CRAM file {}",
+                    self.path.display()
+                )))
+            }
+            None => (),
+        }
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alignments_cram() {
+        let cram = PathBuf::from("testdata/mwgfour-p2-chr3.cram");
+        let fasta = PathBuf::from("testdata/mwgfour-chr3.fa");
+        let alignments = AlignmentFile::new(&cram, Some(&fasta));
+        assert!(alignments.is_cram());
+        assert!(alignments.open().is_ok());
+    }
+
+    #[test]
+    fn test_alignments_cram_no_reference() {
+        let cram = PathBuf::from("testdata/mwgfour-p2-chr3.cram");
+        let alignments = AlignmentFile::new(&cram, None);
+        let message = alignments.open().err().unwrap().to_string();
+        assert!(message.contains("reference FASTA (--reference) is required"));
+    }
+
+    #[test]
+    fn test_alignments_cram_contig_mismatch() {
+        let cram = PathBuf::from("testdata/mwgfour-p2-chr3.cram");
+        let fasta = PathBuf::from("testdata/mwgfour-3.fa");
+        let alignments = AlignmentFile::new(&cram, Some(&fasta));
+        let message = alignments.open().err().unwrap().to_string();
+        assert!(message.contains("sequence 'chr3' in alignment header not found"));
+    }
+
+    #[test]
+    fn test_alignments_aliases() {
+        let bam = PathBuf::from("testdata/mwgfour-p2.bam");
+        let mut alignments = AlignmentFile::new(&bam, None);
+        let reader = alignments.open().unwrap();
+        assert!(alignments.tid(reader.header(), "chr3").is_some());
+        assert!(alignments.tid(reader.header(), "3").is_none());
+        alignments.aliases =
+            ContigAliases::from_csv(Path::new("testdata/ensembl-aliases.csv")).unwrap();
+        assert_eq!(alignments.aliases.resolve("17"), "chr17");
+        assert_eq!(alignments.aliases.resolve("chrX"), "chrX");
+        assert_eq!(
+            alignments.tid(reader.header(), "3"),
+            alignments.tid(reader.header(), "chr3")
+        );
+    }
+
+    #[test]
+    fn test_alignments_read_groups() {
+        let bam = PathBuf::from("testdata/mwgfour-multiplex.bam");
+        let alignments = AlignmentFile::new(&bam, None);
+        let groups = alignments.read_groups(SplitBy::ReadGroup).unwrap();
+        let names: Vec<&String> = groups.keys().collect();
+        assert_eq!(names, vec!["lib1", "lib2a", "lib2b"]);
+        let samples = alignments.read_groups(SplitBy::Sample).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples["Item1"], HashSet::from(["lib1".to_string()]));
+        assert_eq!(
+            samples["Item2"],
+            HashSet::from(["lib2a".to_string(), "lib2b".to_string()])
+        );
+        let bam = PathBuf::from("testdata/mwgfour-p2.bam");
+        let alignments = AlignmentFile::new(&bam, None);
+        let message = alignments
+            .read_groups(SplitBy::Sample)
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            message,
+            "no read groups declared in alignment header testdata/mwgfour-p2.bam"
+        );
+    }
+
+    #[test]
+    fn test_alignments_sample_name() {
+        let bam = PathBuf::from("testdata/mwgfour-item2-header.bam");
+        assert_eq!(
+            AlignmentFile::new(&bam, None).sample_name().unwrap(),
+            "Item2"
+        );
+        let bam = PathBuf::from("testdata/mwgfour-p2.bam");
+        assert_eq!(
+            AlignmentFile::new(&bam, None).sample_name().unwrap(),
+            "mwgfour-p2"
+        );
+        let cram = PathBuf::from("testdata/mwgfour-p2-chr3.cram");
+        assert_eq!(
+            AlignmentFile::new(&cram, None).sample_name().unwrap(),
+            "mwgfour-p2-chr3"
+        );
+        let bam = PathBuf::from("testdata/mwgfour-multiplex.bam");
+        let message = AlignmentFile::new(&bam, None)
+            .sample_name()
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(message.contains("declares multiple samples (Item1, Item2)"));
+    }
+
+    #[test]
+    fn test_alignments_bam() {
+        let bam = PathBuf::from("testdata/mwgfour-p2.bam");
+        let alignments = AlignmentFile::new(&bam, None);
+        assert!(!alignments.is_cram());
+        assert!(alignments.open().is_ok());
+    }
+}
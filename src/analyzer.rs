@@ -8,18 +8,30 @@
 // Development Center.
 // -------------------------------------------------------------------------------------------------
 
+use crate::alignments::{AlignmentFile, ContigAliases, SplitBy};
 use crate::caller::HaplotypeCaller;
-use crate::observer::HaplotypeObserver;
-use crate::panel::MicrohapPanel;
+use crate::error::Result;
+use crate::panel::{MicrohapPanel, PanelIssue};
 use crate::parameters::TypingParameters;
 use crate::profile::MicrohapProfile;
-use std::path::PathBuf;
+use crate::reference::ReferenceFasta;
+use crate::result::MarkerStatus;
+use crate::traversal::PanelTraversal;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 /// Data structure for managing empirical microhaplotype calling across multiple loci for a given sample.
 pub struct MicrohapAnalyzer {
     panel: MicrohapPanel,
     profile: MicrohapProfile,
+    /// Thresholds and read filters applied when typing.
     pub parameters: TypingParameters,
+    /// Number of threads used to read alignments and observe markers.
+    pub threads: usize,
+    /// Reference genome FASTA, required to decode CRAM.
+    pub reference: Option<PathBuf>,
+    /// Chromosome names to use in place of those in the allele definitions.
+    pub aliases: ContigAliases,
 }
 
 impl MicrohapAnalyzer {
@@ -46,33 +58,106 @@ impl MicrohapAnalyzer {
     /// mh04FHL-005.v6,chr4,81722818
     /// mh04FHL-005.v6,chr4,81722856
     /// ```
-    pub fn new(sample_id: &str, csv_path: &PathBuf) -> MicrohapAnalyzer {
-        let panel = MicrohapPanel::from_csv(csv_path).expect("issue parsing panel CSV");
+    pub fn new(sample_id: &str, csv_path: &PathBuf) -> Result<MicrohapAnalyzer> {
+        let panel = MicrohapPanel::from_csv(csv_path)?;
         let profile = MicrohapProfile::new(sample_id);
 
-        MicrohapAnalyzer {
+        Ok(MicrohapAnalyzer {
             panel,
             profile,
             parameters: TypingParameters::defaults(),
+            threads: 1,
+            reference: None,
+            aliases: ContigAliases::default(),
+        })
+    }
+
+    /// Look up the reference allele of every microhap in the `reference` FASTA, so that typing
+    /// results identify the reference haplotype. Chromosome names are mapped with `aliases`, which
+    /// should be set first. Returns warnings for microhaps whose reference allele is unknown or
+    /// includes an N; see `MicrohapPanel::load_reference`. Does nothing if no reference is set.
+    pub fn load_reference(&mut self) -> Result<Vec<PanelIssue>> {
+        match &self.reference {
+            Some(path) => {
+                let reference = ReferenceFasta::from_path(path)?;
+                self.panel.load_reference(&reference, &self.aliases)
+            }
+            None => Ok(Vec::new()),
         }
     }
 
     /// Perform empirical microhap calling analysis using the read alignments in the specified BAM
-    /// file.
-    pub fn process(&mut self, bam_path: &PathBuf) {
-        for (mhid, definition) in self.panel.iter() {
-            let mut observer = HaplotypeObserver::new(definition);
-            observer.call_from_bam(
-                bam_path,
-                self.parameters.min_base_quality,
-                self.parameters.max_depth,
-            );
-            let mut caller = HaplotypeCaller::from_observer(&observer);
+    /// or CRAM file; decoding CRAM requires a `reference` FASTA. All markers are observed in a
+    /// single pass over the alignments, using up to `threads` worker threads; the resulting profile
+    /// does not depend on the number of threads. Markers whose chromosome is absent from the
+    /// alignments are reported with a `contig_not_found` status. The profile is then assessed for
+    /// evidence of a mixture.
+    pub fn process(&mut self, bam_path: &Path) -> Result<()> {
+        let sample_id = self.profile.sample_id().to_string();
+        self.profile = self.process_sample(&sample_id, bam_path)?;
+        Ok(())
+    }
+
+    /// Perform empirical microhap calling as in `process`, but for the given sample ID, returning
+    /// the profile rather than storing it. This allows a single configured analyzer to type many
+    /// samples in turn; the analyzer's own sample ID and profile are not used.
+    pub fn process_sample(&self, sample_id: &str, bam_path: &Path) -> Result<MicrohapProfile> {
+        let alignments = self.alignments(bam_path);
+        let mut traversal = PanelTraversal::new(&self.panel);
+        traversal.call_from_bam(&alignments, &self.parameters, self.threads)?;
+        Ok(self.call_group(&traversal, 0, sample_id))
+    }
+
+    /// Perform empirical microhap calling on multiplexed alignments, producing one profile per
+    /// read group or per sample as declared in the `@RG` lines of the alignment header, with a
+    /// single pass over the alignments. Profiles are named by read group ID or sample (SM) and
+    /// sorted by name; reads without a declared read group are ignored. The analyzer's own sample
+    /// ID and profile are not used.
+    pub fn process_split(&self, bam_path: &Path, split: SplitBy) -> Result<Vec<MicrohapProfile>> {
+        let alignments = self.alignments(bam_path);
+        let groups = alignments.read_groups(split)?;
+        let ids: Vec<HashSet<String>> = groups.values().cloned().collect();
+        let mut traversal = PanelTraversal::split(&self.panel, &ids);
+        traversal.call_from_bam(&alignments, &self.parameters, self.threads)?;
+        let profiles = groups
+            .keys()
+            .enumerate()
+            .map(|(index, name)| self.call_group(&traversal, index, name))
+            .collect();
+        Ok(profiles)
+    }
+
+    /// Alignments at the given path, decoded with the analyzer's reference and contig aliases.
+    fn alignments(&self, bam_path: &Path) -> AlignmentFile {
+        let mut alignments = AlignmentFile::new(bam_path, self.reference.as_ref());
+        alignments.aliases = self.aliases.clone();
+        alignments
+    }
+
+    /// Call genotypes from the observers of the given group, then assess the resulting profile for
+    /// evidence of a mixture.
+    fn call_group(
+        &self,
+        traversal: &PanelTraversal,
+        index: usize,
+        sample_id: &str,
+    ) -> MicrohapProfile {
+        let mut profile = MicrohapProfile::new(sample_id);
+        for (mhid, observer) in traversal.group(index) {
+            let mut caller = HaplotypeCaller::with_kind(observer, self.parameters.caller);
+            caller.qc_limits = self.parameters.qc.clone();
+            caller.rounding = self.parameters.rounding;
+            caller.neighbor_ratio = self.parameters.neighbor_ratio;
             let detection = self.parameters.detection_threshold.get(mhid);
             let analytical = self.parameters.analytical_threshold.get(mhid);
-            let result = caller.apply_filters(detection, analytical);
-            self.profile.add(mhid, result);
+            let mut result = caller.apply_filters(detection, analytical);
+            if !traversal.contig_found(mhid) {
+                result.status = MarkerStatus::ContigNotFound;
+            }
+            profile.add(mhid, result);
         }
+        profile.summarize_mixture(self.parameters.qc.min_mixture_markers);
+        profile
     }
 
     /// Reference to the final microhaplotype profile for this sample.
@@ -84,11 +169,15 @@ impl MicrohapAnalyzer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::caller::CallerKind;
+    use crate::error::Error;
     use crate::read::ReadHaplotype;
+    use crate::result::QcFlag;
 
     #[test]
     fn test_analyzer() {
-        let mut analyzer = MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv"));
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
         analyzer
             .parameters
             .detection_threshold
@@ -97,7 +186,9 @@ mod tests {
             .parameters
             .analytical_threshold
             .insert("mh17FHL-005.v3", 0.0001);
-        analyzer.process(&PathBuf::from("testdata/mwgfour-p2.bam"));
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2.bam"))
+            .unwrap();
         let profile = analyzer.final_profile();
 
         let result1 = profile.get("mh03USC-3qC.v2").unwrap();
@@ -106,6 +197,7 @@ mod tests {
             ReadHaplotype::from_string("CTACTGG"),
         ];
         assert_eq!(result1.genotype, expected);
+        assert!(result1.qc_flags.is_empty());
 
         let result2 = profile.get("mh17FHL-005.v3").unwrap();
         let expected = vec![
@@ -115,5 +207,242 @@ mod tests {
             ReadHaplotype::from_string("GCTTCT"),
         ];
         assert_eq!(result2.genotype, expected);
+        assert_eq!(result2.qc_flags, vec![QcFlag::PossibleMixture]);
+        assert_eq!(profile.mixture().max_alleles, 4);
+        assert_eq!(profile.mixture().min_contributors, 2);
+        assert!(!profile.mixture().likely_mixture);
+    }
+
+    #[test]
+    fn test_analyzer_process_sample() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        let p1 = analyzer
+            .process_sample("Item1", Path::new("testdata/mwgfour-p1.bam"))
+            .unwrap();
+        assert_eq!(p1.sample_id(), "Item1");
+        assert_eq!(analyzer.final_profile().iter().count(), 0);
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p1.bam"))
+            .unwrap();
+        let result = p1.get("mh03USC-3qC.v2").unwrap();
+        let expected = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        assert_eq!(result.genotype, expected.genotype);
+    }
+
+    #[test]
+    fn test_analyzer_qc_discard_rate() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        analyzer.parameters.qc.max_discard_rate = 0.05;
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2.bam"))
+            .unwrap();
+        let result = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        assert_eq!(result.qc_flags, vec![QcFlag::HighDiscardRate]);
+    }
+
+    #[test]
+    fn test_analyzer_likelihood_caller() {
+        let csv_path = PathBuf::from("testdata/mwgfour.csv");
+        let bam_path = PathBuf::from("testdata/mwgfour-p2.bam");
+        let mut threshold = MicrohapAnalyzer::new("Item2", &csv_path).unwrap();
+        threshold.process(&bam_path).unwrap();
+        let mut likelihood = MicrohapAnalyzer::new("Item2", &csv_path).unwrap();
+        likelihood.parameters.caller = CallerKind::Likelihood;
+        likelihood.process(&bam_path).unwrap();
+        for (mhid, result) in likelihood.final_profile().iter() {
+            let expected = threshold.final_profile().get(mhid).unwrap();
+            assert_eq!(result.genotype, expected.genotype);
+            assert!(expected.likelihood.is_none());
+            assert_eq!(result.likelihood.as_ref().unwrap().genotype_quality, 99);
+        }
+    }
+
+    #[test]
+    fn test_analyzer_cram() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour-chr3.csv")).unwrap();
+        analyzer.reference = Some(PathBuf::from("testdata/mwgfour-chr3.fa"));
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2-chr3.cram"))
+            .unwrap();
+        let result = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        let expected = vec![
+            ReadHaplotype::from_string("CCACTGG"),
+            ReadHaplotype::from_string("CTACTGG"),
+        ];
+        assert_eq!(result.genotype, expected);
+        assert!(result.reference.is_none());
+    }
+
+    #[test]
+    fn test_analyzer_reference_haplotype() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour-chr3.csv")).unwrap();
+        analyzer.reference = Some(PathBuf::from("testdata/mwgfour-chr3.fa"));
+        assert!(analyzer.load_reference().unwrap().is_empty());
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2-chr3.cram"))
+            .unwrap();
+        let result = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        let expected = ReadHaplotype::from_string("CTACTGG");
+        assert_eq!(result.reference, Some(expected));
+        assert!(!result.is_homozygous_reference());
+        assert!(result.to_json().contains("\"reference\": \"CTACTGG\""));
+    }
+
+    #[test]
+    fn test_analyzer_cram_no_reference() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour-chr3.csv")).unwrap();
+        let result = analyzer.process(&PathBuf::from("testdata/mwgfour-p2-chr3.cram"));
+        assert!(matches!(result, Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_analyzer_missing_inputs() {
+        let result = MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/bogus.csv"));
+        assert!(matches!(result, Err(Error::Io { .. })));
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        let result = analyzer.process(&PathBuf::from("testdata/bogus.bam"));
+        assert_eq!(result.err().unwrap().exit_code(), 5);
+    }
+
+    #[test]
+    fn test_analyzer_min_mapq() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        analyzer
+            .parameters
+            .min_mapping_quality
+            .insert("mh03USC-3qC.v2", 61);
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2.bam"))
+            .unwrap();
+        let profile = analyzer.final_profile();
+        let result1 = profile.get("mh03USC-3qC.v2").unwrap();
+        assert!(result1.genotype.is_empty());
+        assert_eq!(result1.rejections.mapping_quality, 2460);
+        let result2 = profile.get("mh17FHL-005.v3").unwrap();
+        assert_eq!(result2.genotype.len(), 2);
+        assert_eq!(result2.rejections.mapping_quality, 0);
+    }
+
+    #[test]
+    fn test_analyzer_split() {
+        let analyzer =
+            MicrohapAnalyzer::new("Sample", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        let bam = PathBuf::from("testdata/mwgfour-multiplex.bam");
+        let profiles = analyzer.process_split(&bam, SplitBy::Sample).unwrap();
+        let names: Vec<&str> = profiles.iter().map(|p| p.sample_id()).collect();
+        assert_eq!(names, vec!["Item1", "Item2"]);
+        for (profile, path) in profiles.iter().zip([
+            "testdata/mwgfour-p1-profile.json",
+            "testdata/mwgfour-p2-profile.json",
+        ]) {
+            let expected = MicrohapProfile::from_path(Path::new(path)).unwrap();
+            for (mhid, result) in profile.iter() {
+                assert_eq!(result.genotype, expected.get(mhid).unwrap().genotype);
+            }
+        }
+        let profiles = analyzer.process_split(&bam, SplitBy::ReadGroup).unwrap();
+        let names: Vec<&str> = profiles.iter().map(|p| p.sample_id()).collect();
+        assert_eq!(names, vec!["lib1", "lib2a", "lib2b"]);
+        let result = analyzer
+            .process_split(&PathBuf::from("testdata/mwgfour-p2.bam"), SplitBy::Sample)
+            .err()
+            .unwrap();
+        assert!(matches!(result, Error::Validation(_)));
+    }
+
+    #[test]
+    fn test_analyzer_rejections() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        analyzer.parameters.max_depth = 500;
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2.bam"))
+            .unwrap();
+        let result = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        assert_eq!(result.coverage.max, 500);
+        assert_eq!(result.num_discarded, 62);
+        let rejections = &result.rejections;
+        assert_eq!(rejections.flags, 0);
+        assert_eq!(rejections.mapping_quality, 0);
+        assert_eq!(rejections.base_quality, 50);
+        assert_eq!(rejections.gap, 2);
+        assert_eq!(rejections.partial_span, 10);
+        assert_eq!(rejections.depth_capped, 43);
+    }
+
+    #[test]
+    fn test_analyzer_paired() {
+        let csv = PathBuf::from("testdata/mwgfour-chr3.csv");
+        let bam = PathBuf::from("testdata/mwgfour-p2-chr3-paired.bam");
+        let mut unpaired = MicrohapAnalyzer::new("Item2", &csv).unwrap();
+        unpaired.process(&bam).unwrap();
+        let result = unpaired.final_profile().get("mh03USC-3qC.v2").unwrap();
+        let false_allele = ReadHaplotype::from_string("CAACTGG");
+        assert!(result.genotype.contains(&false_allele));
+        assert_eq!(result.rejections.mate_conflict, 0);
+
+        let mut paired = MicrohapAnalyzer::new("Item2", &csv).unwrap();
+        paired.parameters.paired = true;
+        paired.process(&bam).unwrap();
+        let result = paired.final_profile().get("mh03USC-3qC.v2").unwrap();
+        let expected = vec![
+            ReadHaplotype::from_string("CCACTGG"),
+            ReadHaplotype::from_string("CTACTGG"),
+        ];
+        assert_eq!(result.genotype, expected);
+        assert_eq!(result.rejections.mate_conflict, 41);
+        assert_eq!(*result.counts.tally.get(&expected[0]).unwrap(), 966);
+    }
+
+    #[test]
+    fn test_analyzer_contig_aliases() {
+        let csv = PathBuf::from("testdata/mwgfour-ensembl.csv");
+        let bam = PathBuf::from("testdata/mwgfour-p2.bam");
+        let mut analyzer = MicrohapAnalyzer::new("Item2", &csv).unwrap();
+        analyzer.process(&bam).unwrap();
+        for (_, result) in analyzer.final_profile().iter() {
+            assert_eq!(result.status, MarkerStatus::ContigNotFound);
+            assert!(result.genotype.is_empty());
+        }
+
+        let mut analyzer = MicrohapAnalyzer::new("Item2", &csv).unwrap();
+        analyzer.parameters.strict_contigs = true;
+        let message = analyzer.process(&bam).err().unwrap().to_string();
+        assert!(message.contains("for marker(s) mh03USC-3qC.v2, mh04WL-052.v1,"));
+
+        let mut analyzer = MicrohapAnalyzer::new("Item2", &csv).unwrap();
+        analyzer.parameters.strict_contigs = true;
+        analyzer.aliases =
+            ContigAliases::from_csv(Path::new("testdata/ensembl-aliases.csv")).unwrap();
+        analyzer.process(&bam).unwrap();
+        let result = analyzer.final_profile().get("mh03USC-3qC.v2").unwrap();
+        assert_eq!(result.status, MarkerStatus::Ok);
+        assert_eq!(result.genotype_string(), "CCACTGG/CTACTGG");
+    }
+
+    #[test]
+    fn test_analyzer_no_reads() {
+        let mut analyzer =
+            MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv")).unwrap();
+        analyzer
+            .process(&PathBuf::from("testdata/mwgfour-p2-chr3-paired.bam"))
+            .unwrap();
+        let profile = analyzer.final_profile();
+        let result = profile.get("mh03USC-3qC.v2").unwrap();
+        assert_eq!(result.status, MarkerStatus::NoReads);
+        let result = profile.get("mh04WL-052.v1").unwrap();
+        assert_eq!(result.status, MarkerStatus::ContigNotFound);
+        assert_eq!(result.coverage.mean, 0.0);
+        let profile = MicrohapProfile::from_json(&profile.to_json()).unwrap();
+        let result = profile.get("mh04WL-052.v1").unwrap();
+        assert_eq!(result.status, MarkerStatus::ContigNotFound);
+        assert_eq!(result.coverage.max, 0);
     }
 }
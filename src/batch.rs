@@ -0,0 +1,205 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate csv;
+
+use crate::error::{Error, Result};
+use csv::{ReaderBuilder, StringRecord};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A single sample to be typed in a batch, with optional sample-specific default thresholds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleEntry {
+    pub sample: String,
+    pub alignments: PathBuf,
+    pub detection: Option<u16>,
+    pub analytical: Option<f64>,
+}
+
+/// List of samples to be typed in a batch, loaded from a CSV file with a header row and one row per
+/// sample: the sample name, the path of its BAM or CRAM file, and optionally its detection and
+/// analytical thresholds. Relative alignment paths are resolved against the directory containing
+/// the sample sheet. See Appendix D of the user guide for the expected format.
+pub struct SampleSheet {
+    entries: Vec<SampleEntry>,
+}
+
+impl SampleSheet {
+    /// Load the sample sheet from a CSV file. Sample names must be unique and, since each names an
+    /// output file, must not contain path separators or be `.` or `..`.
+    pub fn from_csv(csv_path: &Path) -> Result<SampleSheet> {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_path(csv_path)
+            .map_err(|e| Error::csv_file(csv_path, e))?;
+        let directory = csv_path.parent().unwrap_or(Path::new(""));
+        let mut entries = Vec::new();
+        let mut names = HashSet::new();
+        for result in reader.records() {
+            let record = result.map_err(|e| Error::csv_file(csv_path, e))?;
+            let position = record.position();
+            let (sample, alignments) = match (record.get(0), record.get(1)) {
+                (Some(sample), Some(alignments)) if !sample.is_empty() => (sample, alignments),
+                _ => return Err(Error::csv(csv_path, position, "expected at least 2 fields")),
+            };
+            if sample == "." || sample == ".." || sample.contains(['/', '\\']) {
+                let message = format!("invalid sample name '{}'", sample);
+                return Err(Error::csv(csv_path, position, &message));
+            }
+            if !names.insert(sample.to_string()) {
+                let message = format!("duplicate sample name '{}'", sample);
+                return Err(Error::csv(csv_path, position, &message));
+            }
+            entries.push(SampleEntry {
+                sample: sample.to_string(),
+                alignments: directory.join(alignments),
+                detection: optional_field(csv_path, &record, 2)?,
+                analytical: optional_field(csv_path, &record, 3)?,
+            });
+        }
+        Ok(SampleSheet { entries })
+    }
+
+    /// Number of samples in the sheet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Indicate whether the sheet lists no samples.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over samples in the order listed.
+    pub fn iter(&self) -> impl Iterator<Item = &SampleEntry> {
+        self.entries.iter()
+    }
+}
+
+/// Parse an optional numeric threshold from the given field of a CSV record; absent or empty fields
+/// yield `None`.
+fn optional_field<T: FromStr>(
+    csv_path: &Path,
+    record: &StringRecord,
+    index: usize,
+) -> Result<Option<T>> {
+    match record.get(index).filter(|value| !value.is_empty()) {
+        None => Ok(None),
+        Some(value) => value.parse::<T>().map(Some).map_err(|_| {
+            let message = format!("invalid threshold '{}'", value);
+            Error::csv(csv_path, record.position(), &message)
+        }),
+    }
+}
+
+/// Outcome of typing each sample in a batch.
+#[derive(Default)]
+pub struct BatchSummary {
+    outcomes: Vec<(String, Option<String>)>,
+}
+
+impl BatchSummary {
+    /// Initialize an empty summary.
+    pub fn new() -> BatchSummary {
+        BatchSummary::default()
+    }
+
+    /// Record the outcome of typing the given sample.
+    pub fn record(&mut self, sample: &str, outcome: &Result<()>) {
+        let error = outcome.as_ref().err().map(|error| error.to_string());
+        self.outcomes.push((sample.to_string(), error));
+    }
+
+    /// Number of samples that could not be typed.
+    pub fn failures(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|(_, error)| error.is_some())
+            .count()
+    }
+
+    /// Tab-separated table with one row per sample: whether typing succeeded and, if not, why.
+    pub fn to_table(&self) -> String {
+        let mut table = String::from("Sample\tResult\tError\n");
+        for (sample, error) in &self.outcomes {
+            let (result, message) = match error {
+                None => ("ok", ""),
+                Some(message) => ("failed", message.as_str()),
+            };
+            table.push_str(&format!("{}\t{}\t{}\n", sample, result, message));
+        }
+        table
+    }
+
+    /// One-line summary of the number of samples typed successfully.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} of {} samples typed successfully",
+            self.outcomes.len() - self.failures(),
+            self.outcomes.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_sample_sheet() {
+        let sheet = SampleSheet::from_csv(Path::new("testdata/mwgfour-samplesheet.csv")).unwrap();
+        assert_eq!(sheet.len(), 3);
+        let entries: Vec<&SampleEntry> = sheet.iter().collect();
+        assert_eq!(entries[0].sample, "Item1");
+        assert_eq!(
+            entries[0].alignments,
+            PathBuf::from("testdata/mwgfour-p1.bam")
+        );
+        assert_eq!(entries[0].detection, None);
+        assert_eq!(entries[1].detection, Some(5));
+        assert_eq!(entries[1].analytical, Some(0.02));
+        assert_eq!(entries[2].alignments, PathBuf::from("testdata/bogus.bam"));
+    }
+
+    #[test]
+    fn test_batch_sample_sheet_duplicate() {
+        let result = SampleSheet::from_csv(Path::new("testdata/mwgfour-samplesheet-bad.csv"));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/mwgfour-samplesheet-bad.csv, line 3: duplicate sample name 'Item1'"
+        );
+    }
+
+    #[test]
+    fn test_batch_sample_sheet_path() {
+        let result = SampleSheet::from_csv(Path::new("testdata/mwgfour-samplesheet-path.csv"));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/mwgfour-samplesheet-path.csv, line 3: invalid sample name '../Item2'"
+        );
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let mut summary = BatchSummary::new();
+        summary.record("s1", &Ok(()));
+        summary.record("s2", &Err(Error::Validation("no reads".to_string())));
+        assert_eq!(summary.failures(), 1);
+        assert_eq!(summary.summary(), "1 of 2 samples typed successfully");
+        assert_eq!(
+            summary.to_table(),
+            "Sample\tResult\tError\ns1\tok\t\ns2\tfailed\tno reads\n"
+        );
+    }
+}
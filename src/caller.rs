@@ -13,14 +13,64 @@ extern crate serde;
 extern crate serde_json;
 
 use crate::counter::ReadHapCounter;
+use crate::error::{Error, Result};
 use crate::observer::HaplotypeObserver;
+use crate::parameters::QcLimits;
 use crate::read::ReadHaplotype;
-use crate::result::{TypingCoverage, TypingResult};
-use crate::thresholds::TypingThresholds;
+use crate::result::{
+    AlleleFrequency, GenotypeLikelihoods, MarkerStatus, QcFlag, ReadRejections, TypingCoverage,
+    TypingResult,
+};
+use crate::thresholds::{ThresholdRounding, TypingThresholds};
+use clap::ValueEnum;
 use counter::Counter;
+use std::collections::BTreeMap;
+
+/// Genotype calling algorithm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum CallerKind {
+    /// Call every allele passing the detection and analytical thresholds
+    #[default]
+    Threshold,
+    /// Call the most likely one- or two-allele genotype given per-base error probabilities
+    Likelihood,
+}
+
+/// Read count ratio below which an allele one substitution from a more abundant allele is rejected
+/// by the error-neighbor filter as a likely sequencing error; always strictly between 0 and 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NeighborRatio(f64);
+
+impl NeighborRatio {
+    /// Initialize with the given ratio, which must be in (0, 1).
+    pub fn new(ratio: f64) -> Result<NeighborRatio> {
+        if !(ratio > 0.0 && ratio < 1.0) {
+            let message = format!("neighbor ratio {} outside the range (0, 1)", ratio);
+            return Err(Error::Validation(message));
+        }
+        Ok(NeighborRatio(ratio))
+    }
+
+    /// Value of the ratio.
+    pub fn get(&self) -> f64 {
+        self.0
+    }
+}
+
+/// Maximum number of candidate alleles considered by the likelihood caller.
+pub const MAX_CANDIDATES: usize = 8;
 
 /// A data structure for applying read coverage thresholds to empirically observed haplotypes to
 /// produce a typing result for a single microhap.
+///
+/// Alternatively, the likelihood caller treats each allele passing the detection threshold as a
+/// candidate and computes the likelihood of every homozygous and heterozygous genotype of
+/// candidates. Each read is assumed to originate from either allele of the genotype with equal
+/// probability, and each base is assumed to be a sequencing error with the probability given by
+/// its quality score, in which case any of the three other nucleotides is equally likely. The most
+/// likely genotype is called, with a genotype quality derived from its posterior probability under
+/// a uniform prior. Since the number of genotypes grows quadratically with the number of
+/// candidates, only the [`MAX_CANDIDATES`] alleles with the most reads are considered.
 pub struct HaplotypeCaller {
     detection_filter: u16,
     analytical_filter: f64,
@@ -28,11 +78,28 @@ pub struct HaplotypeCaller {
     counts: Counter<ReadHaplotype>,
     coverage: TypingCoverage,
     discarded: usize,
+    rejections: ReadRejections,
+    reads: Vec<(ReadHaplotype, Vec<u8>, usize)>,
+    reference: Option<ReadHaplotype>,
+    kind: CallerKind,
+    pub qc_limits: QcLimits,
+    pub rounding: ThresholdRounding,
+    pub neighbor_ratio: Option<NeighborRatio>,
 }
 
 impl HaplotypeCaller {
     /// Initialize from a HaplotypeObserver
     pub fn from_observer(observer: &HaplotypeObserver) -> HaplotypeCaller {
+        HaplotypeCaller::with_kind(observer, CallerKind::default())
+    }
+
+    /// Initialize from a HaplotypeObserver for the given calling algorithm. Individual reads are
+    /// retained only for the likelihood caller.
+    pub fn with_kind(observer: &HaplotypeObserver, kind: CallerKind) -> HaplotypeCaller {
+        let reads = match kind {
+            CallerKind::Threshold => Vec::new(),
+            CallerKind::Likelihood => observer.complete_reads(),
+        };
         let (max, mean, min) = observer.coverage();
         let coverage = TypingCoverage { max, mean, min };
         HaplotypeCaller {
@@ -42,34 +109,235 @@ impl HaplotypeCaller {
             counts: observer.count(),
             coverage,
             discarded: observer.discarded(),
+            rejections: observer.rejections(),
+            reads,
+            reference: observer.definition().reference.clone(),
+            kind,
+            qc_limits: QcLimits::default(),
+            rounding: ThresholdRounding::default(),
+            neighbor_ratio: None,
         }
     }
 
+    /// Genotype calling algorithm.
+    pub fn kind(&self) -> CallerKind {
+        self.kind
+    }
+
+    /// Call the genotype by applying the detection threshold (a minimum read count) and then the
+    /// analytical threshold (a minimum fraction of the reads that pass the detection threshold),
+    /// compared against read counts according to `rounding`. The likelihood caller applies only
+    /// the detection threshold, and reports both analytical thresholds as zero. If `neighbor_ratio`
+    /// is set, alleles passing the thresholds are then screened for likely sequencing errors with
+    /// the error-neighbor filter before the genotype is called. Allele frequencies
+    /// and heterozygote balance are reported alongside the call, and the result is flagged for
+    /// review according to `qc_limits`.
     pub fn apply_filters(&mut self, detection: u16, analytical: f64) -> TypingResult {
         self.detection_filter = detection;
         self.analytical_filter = analytical;
         let detect = self.apply_detection_threshold();
-        let analy = self.apply_analytical_threshold();
-        let mut genotype: Vec<ReadHaplotype> = self.counts.keys().cloned().collect();
-        genotype.sort();
+        let (dynamic, analy) = match self.kind {
+            CallerKind::Threshold => (analytical, self.apply_analytical_threshold()),
+            CallerKind::Likelihood => (0.0, 0.0),
+        };
+        let error_neighbors = self.apply_neighbor_filter();
+        let (genotype, likelihood) = match self.kind {
+            CallerKind::Threshold => {
+                let mut genotype: Vec<ReadHaplotype> = self.counts.keys().cloned().collect();
+                genotype.sort();
+                (genotype, None)
+            }
+            CallerKind::Likelihood => match self.genotype_likelihoods() {
+                Some((genotype, likelihood)) => (genotype, Some(likelihood)),
+                None => (Vec::new(), None),
+            },
+        };
 
         let thresholds = TypingThresholds {
-            dynamic: analytical,
+            dynamic,
             analytical: analy,
             detection: detect,
+            rounding: self.rounding,
         };
+        let het_balance = self.het_balance(&genotype);
+        let frequencies = self.frequencies();
+        let qc_flags = self.qc_flags(&genotype, het_balance);
         let counts = ReadHapCounter {
             tally: self.raw_counts.clone(),
         };
+        let status = match self.coverage.max {
+            0 => MarkerStatus::NoReads,
+            _ => MarkerStatus::Ok,
+        };
         TypingResult {
+            status,
             genotype,
+            reference: self.reference.clone(),
             coverage: self.coverage.clone(),
             num_discarded: self.discarded,
+            rejections: self.rejections.clone(),
             thresholds,
+            likelihood,
+            error_neighbors,
+            het_balance,
+            frequencies,
+            qc_flags,
             counts,
         }
     }
 
+    /// Reject alleles that differ at a single ADS from another allele with a read count more than
+    /// `1 / neighbor_ratio` times higher, since such alleles most likely result from a sequencing
+    /// error in reads of the more abundant allele. Each rejected allele is returned with the allele
+    /// it is attributed to: its most abundant qualifying neighbor.
+    fn apply_neighbor_filter(&mut self) -> BTreeMap<ReadHaplotype, ReadHaplotype> {
+        let ratio = match self.neighbor_ratio {
+            Some(ratio) => ratio.get(),
+            None => return BTreeMap::new(),
+        };
+        let mut alleles: Vec<(&ReadHaplotype, &usize)> = self.counts.iter().collect();
+        alleles.sort_by(|(allele1, count1), (allele2, count2)| {
+            count2.cmp(count1).then(allele1.cmp(allele2))
+        });
+        let mut error_neighbors = BTreeMap::new();
+        for (allele, count) in &alleles {
+            let parent = alleles.iter().find(|(parent, parent_count)| {
+                allele.hamming_distance(parent) == 1
+                    && (**count as f64) < ratio * **parent_count as f64
+            });
+            if let Some((parent, _)) = parent {
+                error_neighbors.insert((*allele).clone(), (*parent).clone());
+            }
+        }
+        self.counts
+            .retain(|allele, _| !error_neighbors.contains_key(allele));
+        error_neighbors
+    }
+
+    /// Compute the likelihood of every genotype of candidate alleles, returning the most likely
+    /// genotype, or `None` if there are no candidates. Candidates are limited to the
+    /// [`MAX_CANDIDATES`] alleles with the most reads.
+    fn genotype_likelihoods(&self) -> Option<(Vec<ReadHaplotype>, GenotypeLikelihoods)> {
+        let mut alleles: Vec<(&ReadHaplotype, &usize)> = self.counts.iter().collect();
+        if alleles.is_empty() {
+            return None;
+        }
+        alleles.sort_by(|(allele1, count1), (allele2, count2)| {
+            count2.cmp(count1).then(allele1.cmp(allele2))
+        });
+        let mut candidates: Vec<ReadHaplotype> = alleles
+            .into_iter()
+            .take(MAX_CANDIDATES)
+            .map(|(allele, _)| allele.clone())
+            .collect();
+        candidates.sort();
+        let read_likelihoods: Vec<(Vec<f64>, f64)> = self
+            .reads
+            .iter()
+            .map(|(haplotype, qualities, count)| {
+                let likelihoods = candidates
+                    .iter()
+                    .map(|allele| read_likelihood(haplotype, qualities, allele))
+                    .collect();
+                (likelihoods, *count as f64)
+            })
+            .collect();
+        let mut genotypes = Vec::new();
+        for i in 0..candidates.len() {
+            for j in i..candidates.len() {
+                let log10_likelihood: f64 = read_likelihoods
+                    .iter()
+                    .map(|(likelihoods, count)| {
+                        count * (0.5 * (likelihoods[i] + likelihoods[j])).log10()
+                    })
+                    .sum();
+                genotypes.push(((i, j), log10_likelihood));
+            }
+        }
+        let ((best_i, best_j), best) = genotypes
+            .iter()
+            .copied()
+            .max_by(|(_, ll1), (_, ll2)| ll1.total_cmp(ll2))
+            .unwrap();
+        let others: f64 = genotypes
+            .iter()
+            .filter(|(alleles, _)| *alleles != (best_i, best_j))
+            .map(|(_, ll)| 10f64.powf(ll - best))
+            .sum();
+        let quality = -10.0 * (others / (1.0 + others)).log10();
+        let genotype_quality = if quality.is_finite() {
+            quality.round().min(99.0) as u32
+        } else {
+            99
+        };
+        let log10_likelihoods = genotypes
+            .iter()
+            .map(|((i, j), ll)| (format!("{}/{}", candidates[*i], candidates[*j]), *ll))
+            .collect();
+        let mut genotype = vec![candidates[best_i].clone()];
+        if best_j != best_i {
+            genotype.push(candidates[best_j].clone());
+        }
+        let likelihoods = GenotypeLikelihoods {
+            genotype_quality,
+            log10_likelihoods,
+        };
+        Some((genotype, likelihoods))
+    }
+
+    /// Frequency of each observed allele relative to the reads supporting called alleles and to
+    /// all reads spanning the marker.
+    fn frequencies(&self) -> BTreeMap<ReadHaplotype, AlleleFrequency> {
+        let passing = self.counts.total::<usize>() as f64;
+        let spanning = self.raw_counts.total::<usize>() as f64;
+        self.raw_counts
+            .iter()
+            .map(|(allele, count)| {
+                let count = *count as f64;
+                let frequency = AlleleFrequency {
+                    passing: if passing > 0.0 { count / passing } else { 0.0 },
+                    spanning: count / spanning,
+                };
+                (allele.clone(), frequency)
+            })
+            .collect()
+    }
+
+    /// Ratio of the minor to the major allele read count for a heterozygous call; `None` for any
+    /// other number of called alleles.
+    fn het_balance(&self, genotype: &[ReadHaplotype]) -> Option<f64> {
+        if genotype.len() != 2 {
+            return None;
+        }
+        let count1 = self.counts[&genotype[0]] as f64;
+        let count2 = self.counts[&genotype[1]] as f64;
+        Some(count1.min(count2) / count1.max(count2))
+    }
+
+    /// Raise QC flags for the called genotype.
+    fn qc_flags(&self, genotype: &[ReadHaplotype], het_balance: Option<f64>) -> Vec<QcFlag> {
+        let limits = &self.qc_limits;
+        let mut flags = Vec::new();
+        let total = self.raw_counts.total::<usize>();
+        if total < limits.min_coverage {
+            flags.push(QcFlag::LowCoverage);
+        }
+        if genotype.is_empty() {
+            flags.push(QcFlag::NoAllelesPassing);
+        }
+        if genotype.len() > limits.max_alleles {
+            flags.push(QcFlag::PossibleMixture);
+        }
+        if het_balance.is_some_and(|balance| balance < limits.min_het_balance) {
+            flags.push(QcFlag::HeterozygoteImbalance);
+        }
+        let observed = total + self.discarded;
+        if observed > 0 && self.discarded as f64 / observed as f64 > limits.max_discard_rate {
+            flags.push(QcFlag::HighDiscardRate);
+        }
+        flags
+    }
+
     fn apply_detection_threshold(&mut self) -> u16 {
         let static_threshold = self.detection_filter;
         self.counts
@@ -80,18 +348,37 @@ impl HaplotypeCaller {
     fn apply_analytical_threshold(&mut self) -> f64 {
         let count_total: usize = self.counts.total();
         let dynamic_threshold = (count_total as f64) * self.analytical_filter;
+        let rounding = self.rounding;
         self.counts
-            .retain(|_, count| *count >= dynamic_threshold as usize);
+            .retain(|_, count| rounding.passes(*count, dynamic_threshold));
         dynamic_threshold
     }
 }
 
+/// Probability of observing the given read haplotype, with the given base qualities, if the read
+/// originates from the given allele.
+fn read_likelihood(haplotype: &ReadHaplotype, qualities: &[u8], allele: &ReadHaplotype) -> f64 {
+    haplotype
+        .iter()
+        .zip(allele.iter())
+        .zip(qualities)
+        .map(|((observed, expected), quality)| {
+            let error = 10f64.powf(-(*quality as f64) / 10.0).min(0.75);
+            if observed == expected {
+                1.0 - error
+            } else {
+                error / 3.0
+            }
+        })
+        .product()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::definition::AlleleDefinition;
 
-    fn init_caller() -> HaplotypeCaller {
+    fn init_observer() -> HaplotypeObserver {
         let def = AlleleDefinition::from_vector(
             "chr22",
             vec![48665164, 48665175, 48665182, 48665204, 48665216],
@@ -101,8 +388,15 @@ mod tests {
         observer.set_all("read2", "AATAT");
         observer.set_all("read3", "ACGAT");
         observer.set_all("read4", "AATAT");
+        observer
+    }
 
-        HaplotypeCaller::from_observer(&observer)
+    fn init_caller() -> HaplotypeCaller {
+        HaplotypeCaller::from_observer(&init_observer())
+    }
+
+    fn init_likelihood_caller() -> HaplotypeCaller {
+        HaplotypeCaller::with_kind(&init_observer(), CallerKind::Likelihood)
     }
 
     #[test]
@@ -113,4 +407,181 @@ mod tests {
         assert_eq!(*caller.raw_counts.get(&readhap1).unwrap(), 3);
         assert_eq!(*caller.raw_counts.get(&readhap2).unwrap(), 1);
     }
+
+    #[test]
+    fn test_typing_qc_flags() {
+        let mut caller = init_caller();
+        let result = caller.apply_filters(1, 0.02);
+        let expected = vec![QcFlag::LowCoverage, QcFlag::HeterozygoteImbalance];
+        assert_eq!(result.qc_flags, expected);
+
+        let mut caller = init_caller();
+        caller.qc_limits.min_coverage = 4;
+        caller.qc_limits.max_alleles = 1;
+        caller.qc_limits.min_het_balance = 0.3;
+        let result = caller.apply_filters(1, 0.02);
+        assert_eq!(result.qc_flags, vec![QcFlag::PossibleMixture]);
+
+        let mut caller = init_caller();
+        let result = caller.apply_filters(5, 0.02);
+        let expected = vec![QcFlag::LowCoverage, QcFlag::NoAllelesPassing];
+        assert_eq!(result.qc_flags, expected);
+    }
+
+    #[test]
+    fn test_typing_analytical_boundary() {
+        let allele1 = ReadHaplotype::from_string("AATAT");
+        let allele2 = ReadHaplotype::from_string("ACGAT");
+        let mut caller = init_caller();
+        let result = caller.apply_filters(1, 0.3);
+        assert_eq!(result.thresholds.analytical, 4.0 * 0.3);
+        assert_eq!(result.thresholds.rounding, ThresholdRounding::Exact);
+        assert_eq!(result.genotype, vec![allele1.clone()]);
+
+        let mut caller = init_caller();
+        caller.rounding = ThresholdRounding::Floor;
+        let result = caller.apply_filters(1, 0.3);
+        assert_eq!(result.thresholds.rounding, ThresholdRounding::Floor);
+        assert_eq!(result.genotype, vec![allele1, allele2]);
+    }
+
+    #[test]
+    fn test_typing_likelihood() {
+        let mut caller = init_likelihood_caller();
+        let result = caller.apply_filters(1, 0.3);
+        let allele1 = ReadHaplotype::from_string("AATAT");
+        let allele2 = ReadHaplotype::from_string("ACGAT");
+        assert_eq!(result.genotype, vec![allele1.clone(), allele2]);
+        assert_eq!(result.thresholds.analytical, 0.0);
+        let likelihood = result.likelihood.unwrap();
+        assert_eq!(likelihood.genotype_quality, 57);
+        assert_eq!(likelihood.log10_likelihoods.len(), 3);
+        let het = likelihood.log10_likelihoods["AATAT/ACGAT"];
+        assert!(het > likelihood.log10_likelihoods["AATAT/AATAT"]);
+        assert!(het > likelihood.log10_likelihoods["ACGAT/ACGAT"]);
+
+        let mut caller = init_likelihood_caller();
+        let result = caller.apply_filters(2, 0.3);
+        assert_eq!(result.genotype, vec![allele1]);
+        assert_eq!(result.likelihood.unwrap().log10_likelihoods.len(), 1);
+
+        let mut caller = init_likelihood_caller();
+        let result = caller.apply_filters(5, 0.3);
+        assert!(result.genotype.is_empty());
+        assert!(result.likelihood.is_none());
+    }
+
+    #[test]
+    fn test_typing_likelihood_reads() {
+        let caller = init_caller();
+        assert_eq!(caller.kind(), CallerKind::Threshold);
+        assert!(caller.reads.is_empty());
+
+        let caller = init_likelihood_caller();
+        assert_eq!(caller.kind(), CallerKind::Likelihood);
+        let counts: Vec<usize> = caller.reads.iter().map(|(_, _, count)| *count).collect();
+        assert_eq!(counts, vec![3, 1]);
+    }
+
+    #[test]
+    fn test_typing_likelihood_max_candidates() {
+        let def = AlleleDefinition::from_vector("chr22", vec![48665164, 48665175, 48665182]);
+        let mut observer = HaplotypeObserver::new(&def);
+        let alleles = [
+            "ACG", "ACT", "AGG", "ATG", "CCG", "GCG", "TCG", "ACA", "ACC", "AAG",
+        ];
+        for (index, allele) in alleles.iter().enumerate() {
+            for i in 0..(20 - index) {
+                observer.set_all(&format!("{}-{}", allele, i), allele);
+            }
+        }
+        let mut caller = HaplotypeCaller::with_kind(&observer, CallerKind::Likelihood);
+        let result = caller.apply_filters(1, 0.0);
+        let likelihood = result.likelihood.unwrap();
+        assert_eq!(
+            likelihood.log10_likelihoods.len(),
+            MAX_CANDIDATES * (MAX_CANDIDATES + 1) / 2
+        );
+        assert!(!likelihood.log10_likelihoods.contains_key("AAG/AAG"));
+        assert!(!likelihood.log10_likelihoods.contains_key("ACC/ACC"));
+        assert!(likelihood.log10_likelihoods.contains_key("ACA/ACA"));
+    }
+
+    #[test]
+    fn test_typing_neighbor_filter() {
+        let def = AlleleDefinition::from_vector("chr22", vec![48665164, 48665175, 48665182]);
+        let mut observer = HaplotypeObserver::new(&def);
+        for (allele, count) in [("ACG", 40), ("ATG", 3), ("GCT", 30), ("GCA", 2), ("TTT", 1)] {
+            for i in 0..count {
+                observer.set_all(&format!("{}-{}", allele, i), allele);
+            }
+        }
+        let mut caller = HaplotypeCaller::from_observer(&observer);
+        let result = caller.apply_filters(1, 0.0);
+        assert_eq!(result.genotype.len(), 5);
+        assert!(result.error_neighbors.is_empty());
+
+        let mut caller = HaplotypeCaller::from_observer(&observer);
+        caller.neighbor_ratio = Some(NeighborRatio::new(0.1).unwrap());
+        let result = caller.apply_filters(1, 0.0);
+        let expected = vec![
+            ReadHaplotype::from_string("ACG"),
+            ReadHaplotype::from_string("GCT"),
+            ReadHaplotype::from_string("TTT"),
+        ];
+        assert_eq!(result.genotype, expected);
+        let parent = &result.error_neighbors[&ReadHaplotype::from_string("ATG")];
+        assert_eq!(*parent, ReadHaplotype::from_string("ACG"));
+        let parent = &result.error_neighbors[&ReadHaplotype::from_string("GCA")];
+        assert_eq!(*parent, ReadHaplotype::from_string("GCT"));
+        assert_eq!(result.error_neighbors.len(), 2);
+    }
+
+    #[test]
+    fn test_typing_neighbor_ratio() {
+        for ratio in [0.0, 1.0, 1.5, -0.1, f64::NAN] {
+            assert!(matches!(
+                NeighborRatio::new(ratio),
+                Err(Error::Validation(_))
+            ));
+        }
+        let message = NeighborRatio::new(2.0).err().unwrap().to_string();
+        assert_eq!(message, "neighbor ratio 2 outside the range (0, 1)");
+
+        let def = AlleleDefinition::from_vector("chr22", vec![48665164, 48665175, 48665182]);
+        let mut observer = HaplotypeObserver::new(&def);
+        for (allele, count) in [("ACG", 20), ("ATG", 20)] {
+            for i in 0..count {
+                observer.set_all(&format!("{}-{}", allele, i), allele);
+            }
+        }
+        let mut caller = HaplotypeCaller::from_observer(&observer);
+        caller.neighbor_ratio = Some(NeighborRatio::new(0.9).unwrap());
+        let result = caller.apply_filters(1, 0.0);
+        let expected = vec![
+            ReadHaplotype::from_string("ACG"),
+            ReadHaplotype::from_string("ATG"),
+        ];
+        assert_eq!(result.genotype, expected);
+        assert!(result.error_neighbors.is_empty());
+    }
+
+    #[test]
+    fn test_typing_frequencies() {
+        let mut caller = init_caller();
+        let result = caller.apply_filters(1, 0.02);
+        assert_eq!(result.het_balance, Some(1.0 / 3.0));
+        let frequency = &result.frequencies[&ReadHaplotype::from_string("ACGAT")];
+        assert_eq!(frequency.passing, 0.25);
+        assert_eq!(frequency.spanning, 0.25);
+
+        let mut caller = init_caller();
+        let result = caller.apply_filters(2, 0.02);
+        assert_eq!(result.het_balance, None);
+        let frequency = &result.frequencies[&ReadHaplotype::from_string("AATAT")];
+        assert_eq!(frequency.passing, 1.0);
+        assert_eq!(frequency.spanning, 0.75);
+        let frequency = &result.frequencies[&ReadHaplotype::from_string("ACGAT")];
+        assert_eq!(frequency.passing, 1.0 / 3.0);
+    }
 }
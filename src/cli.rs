@@ -8,26 +8,176 @@
 // Development Center.
 // -------------------------------------------------------------------------------------------------
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use mhrs::alignments::SplitBy;
+use mhrs::caller::CallerKind;
+use mhrs::export::{TableFormat, TableLayout};
+use mhrs::thresholds::ThresholdRounding;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
+/// Names and flags recognized at the top level; any other first argument is treated as the start
+/// of a legacy `mhrs defn.csv aligns.bam` invocation.
+const TOP_LEVEL: [&str; 14] = [
+    "type",
+    "batch",
+    "export",
+    "vcf",
+    "compare",
+    "mixture",
+    "rmp",
+    "kinship",
+    "validate-panel",
+    "help",
+    "-h",
+    "--help",
+    "-V",
+    "--version",
+];
+
 #[derive(Parser)]
 #[command(author, version, about = "Empirical microhaplotype caller", long_about = None)]
+#[command(arg_required_else_help = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parse command-line arguments, treating `mhrs defn.csv aligns.bam [options]` as shorthand for
+    /// `mhrs type defn.csv aligns.bam [options]`.
+    pub fn parse_with_legacy<I, T>(args: I) -> Cli
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        if let Some(first) = args.get(1) {
+            if !TOP_LEVEL.iter().any(|name| first == *name) {
+                args.insert(1, OsString::from("type"));
+            }
+        }
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Type all microhaps in a panel from read alignments (the default)
+    Type(TypeArgs),
+
+    /// Type every sample listed in a sample sheet, writing one profile per sample
+    Batch(BatchArgs),
+
+    /// Convert one or more profiles from JSON to a flat CSV or TSV table
+    Export(ExportArgs),
+
+    /// Write the genotype calls of one or more profiles in VCF format
+    Vcf(VcfArgs),
+
+    /// Compare the genotype calls of two profiles marker by marker
+    Compare(CompareArgs),
+
+    /// Estimate the mixture proportion and contributor genotypes of a two-person mixture
+    Mixture(MixtureArgs),
+
+    /// Compute the random match probability of a single-source profile
+    Rmp(RmpArgs),
+
+    /// Compute likelihood ratios for the relationship between two single-source profiles
+    Kinship(KinshipArgs),
+
+    /// Check allele definitions for errors before typing
+    ValidatePanel(ValidatePanelArgs),
+}
+
+#[derive(Args)]
+pub struct TypeArgs {
     #[arg(help = "Microhap allele definitions in CSV format")]
     pub csv: PathBuf,
 
-    #[arg(help = "Read alignments in BAM format")]
+    #[arg(help = "Read alignments in BAM or CRAM format")]
     pub bam: PathBuf,
 
     #[arg(
         short = 'n',
         long = "name",
         value_name = "SN",
-        default_value = "Sample",
-        help = "Sample name"
+        conflicts_with = "split_by",
+        help = "Sample name [default: the SM field of the alignment header's @RG lines, or the alignment file name]"
     )]
-    pub sample: String,
+    pub sample: Option<String>,
+
+    #[arg(
+        long = "split-by",
+        value_name = "SB",
+        help = "Type each read group, or each sample (SM) declared in the alignment header's @RG lines, separately in a single pass, printing the profiles as a JSON array"
+    )]
+    pub split_by: Option<SplitBy>,
+
+    #[command(flatten)]
+    pub typing: TypingArgs,
+}
+
+#[derive(Args)]
+pub struct BatchArgs {
+    #[arg(help = "Microhap allele definitions in CSV format")]
+    pub csv: PathBuf,
+
+    #[arg(help = "Sample names, alignment paths, and optional thresholds in CSV format")]
+    pub samplesheet: PathBuf,
+
+    #[arg(
+        short = 'o',
+        long = "outdir",
+        value_name = "DIR",
+        default_value = ".",
+        help = "Write each profile to DIR/<sample>.json"
+    )]
+    pub outdir: PathBuf,
+
+    #[arg(
+        long = "combined",
+        value_name = "FILE",
+        help = "Write all profiles to FILE as a single JSON array instead of one file per sample"
+    )]
+    pub combined: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub typing: TypingArgs,
+}
+
+#[derive(Args)]
+pub struct TypingArgs {
+    #[arg(
+        short = 'r',
+        long = "reference",
+        value_name = "FA",
+        help = "Reference genome in FASTA format, indexed with samtools faidx; required for CRAM input, and used to report the reference haplotype of each marker"
+    )]
+    pub reference: Option<PathBuf>,
+
+    #[arg(
+        short = 'a',
+        long = "contig-aliases",
+        value_name = "CA",
+        help = "CSV file mapping chromosome names in the allele definitions (column 1) to those in the alignments (column 2), e.g. 1,chr1"
+    )]
+    pub contig_aliases: Option<PathBuf>,
+
+    #[arg(
+        long = "strict-contigs",
+        help = "Fail if any marker's chromosome is absent from the alignment header, rather than skipping the marker"
+    )]
+    pub strict_contigs: bool,
+
+    #[arg(
+        long = "caller",
+        value_name = "GC",
+        default_value = "threshold",
+        help = "Genotype calling algorithm"
+    )]
+    pub caller: CallerKind,
 
     #[arg(
         short = 's',
@@ -47,11 +197,26 @@ pub struct Cli {
     )]
     pub analytical_threshold: f64,
 
+    #[arg(
+        long = "threshold-rounding",
+        value_name = "RR",
+        default_value = "exact",
+        help = "Rule for comparing read counts against the analytical threshold"
+    )]
+    pub rounding: ThresholdRounding,
+
+    #[arg(
+        long = "neighbor-ratio",
+        value_name = "NR",
+        help = "Reject alleles one substitution away from an allele with a read count more than 1/NR times higher, as likely sequencing errors; NR must be between 0 and 1"
+    )]
+    pub neighbor_ratio: Option<f64>,
+
     #[arg(
         short = 't',
         long = "threshold-csv",
         value_name = "TC",
-        help = "CSV file with marker-specific thresholds; column 1 = marker name, column 2 = detection, column 3 = analytical"
+        help = "CSV file with marker-specific thresholds; column 1 = marker name, column 2 = detection, column 3 = analytical, column 4 (optional) = minimum mapping quality"
     )]
     pub threshold_csv: Option<PathBuf>,
 
@@ -64,28 +229,436 @@ pub struct Cli {
     )]
     pub min_base_quality: u8,
 
+    #[arg(
+        short = 'q',
+        long = "min-mapq",
+        value_name = "MQ",
+        default_value = "0",
+        help = "Minimum mapping quality for reads contributing to haplotype calling"
+    )]
+    pub min_mapq: u8,
+
     #[arg(
         short = 'x',
         long = "max-depth",
         value_name = "MD",
         default_value = "1000000",
-        help = "Maximum per-base read depth"
+        help = "Maximum number of reads observed at each ADS"
     )]
     pub max_depth: u32,
+
+    #[arg(
+        long = "paired",
+        help = "Combine the two mates of each unmerged read pair into a single fragment haplotype"
+    )]
+    pub paired: bool,
+
+    #[arg(
+        long = "min-coverage",
+        value_name = "MC",
+        default_value = "20",
+        help = "Flag markers with fewer reads contributing a complete haplotype"
+    )]
+    pub min_coverage: usize,
+
+    #[arg(
+        long = "max-alleles",
+        value_name = "MA",
+        default_value = "2",
+        help = "Flag markers with more alleles called as a possible mixture"
+    )]
+    pub max_alleles: usize,
+
+    #[arg(
+        long = "min-het-balance",
+        value_name = "HB",
+        default_value = "0.5",
+        help = "Flag heterozygous markers whose minor-to-major allele read count ratio is lower"
+    )]
+    pub min_het_balance: f64,
+
+    #[arg(
+        long = "max-discard-rate",
+        value_name = "DR",
+        default_value = "0.25",
+        help = "Flag markers with a higher fraction of reads discarded as partial haplotypes"
+    )]
+    pub max_discard_rate: f64,
+
+    #[arg(
+        long = "mixture-markers",
+        value_name = "MM",
+        default_value = "2",
+        help = "Flag the profile as a likely mixture if at least this many markers have more than two alleles called"
+    )]
+    pub mixture_markers: usize,
+
+    #[arg(
+        short = 'p',
+        long = "threads",
+        value_name = "NT",
+        default_value = "1",
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of threads for marker typing and BAM decompression"
+    )]
+    pub threads: u16,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(
+        required = true,
+        help = "Microhap profiles in JSON format; a file may hold a single profile or an array of profiles"
+    )]
+    pub profiles: Vec<PathBuf>,
+
+    #[arg(
+        short = 'l',
+        long = "layout",
+        value_name = "LT",
+        default_value = "long",
+        help = "Table layout"
+    )]
+    pub layout: TableLayout,
+
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_name = "FMT",
+        default_value = "csv",
+        help = "Table format"
+    )]
+    pub format: TableFormat,
+
+    #[arg(
+        short = 'o',
+        long = "out",
+        value_name = "FILE",
+        help = "Write the table to FILE instead of the terminal (standard output)"
+    )]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct VcfArgs {
+    #[arg(help = "Microhap allele definitions in CSV format")]
+    pub csv: PathBuf,
+
+    #[arg(
+        required = true,
+        help = "Microhap profiles in JSON format, one sample column each; a file may hold a single profile or an array of profiles"
+    )]
+    pub profiles: Vec<PathBuf>,
+
+    #[arg(
+        short = 'o',
+        long = "out",
+        value_name = "FILE",
+        help = "Write the VCF to FILE instead of the terminal (standard output)"
+    )]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    #[arg(help = "First microhap profile in JSON format, or an array holding only that profile")]
+    pub first: PathBuf,
+
+    #[arg(help = "Second microhap profile in JSON format, or an array holding only that profile")]
+    pub second: PathBuf,
+}
+
+#[derive(Args)]
+pub struct MixtureArgs {
+    #[arg(
+        help = "Microhap profile of a two-person mixture in JSON format, or an array holding only that profile"
+    )]
+    pub profile: PathBuf,
+
+    #[arg(
+        short = 'k',
+        long = "known",
+        value_name = "KP",
+        help = "Microhap profile of a known contributor in JSON format, or an array holding only that profile"
+    )]
+    pub known: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct RmpArgs {
+    #[arg(help = "Microhap profile in JSON format, or an array holding only that profile")]
+    pub profile: PathBuf,
+
+    #[arg(help = "Population allele frequencies in CSV format")]
+    pub frequencies: PathBuf,
+
+    #[arg(
+        short = 'P',
+        long = "population",
+        value_name = "POP",
+        help = "Population for which to compute the RMP; may be repeated; default is all populations in the frequency table"
+    )]
+    pub populations: Vec<String>,
+
+    #[arg(
+        long = "theta",
+        value_name = "FST",
+        default_value = "0.01",
+        help = "Coancestry coefficient correcting for population substructure"
+    )]
+    pub theta: f64,
+
+    #[arg(
+        long = "min-frequency",
+        value_name = "MF",
+        default_value = "0.001",
+        help = "Frequency assigned to alleles absent from or rarer than this in the frequency table"
+    )]
+    pub min_frequency: f64,
+}
+
+#[derive(Args)]
+pub struct KinshipArgs {
+    #[arg(help = "First microhap profile in JSON format, or an array holding only that profile")]
+    pub first: PathBuf,
+
+    #[arg(help = "Second microhap profile in JSON format, or an array holding only that profile")]
+    pub second: PathBuf,
+
+    #[arg(help = "Population allele frequencies in CSV format")]
+    pub frequencies: PathBuf,
+
+    #[arg(
+        short = 'P',
+        long = "population",
+        value_name = "POP",
+        help = "Population whose allele frequencies are used"
+    )]
+    pub population: String,
+
+    #[arg(
+        long = "allowance",
+        value_name = "MU",
+        default_value = "0.001",
+        help = "Probability that an allele shared by descent is not observed due to mutation or dropout"
+    )]
+    pub allowance: f64,
+
+    #[arg(
+        long = "min-frequency",
+        value_name = "MF",
+        default_value = "0.001",
+        help = "Frequency assigned to alleles absent from or rarer than this in the frequency table"
+    )]
+    pub min_frequency: f64,
+}
+
+#[derive(Args)]
+pub struct ValidatePanelArgs {
+    #[arg(help = "Microhap allele definitions in CSV format")]
+    pub csv: PathBuf,
+
+    #[arg(
+        short = 'r',
+        long = "reference",
+        value_name = "FA",
+        help = "Reference genome in FASTA format, indexed with samtools faidx; used to check chromosome names and ADS coordinates"
+    )]
+    pub reference: Option<PathBuf>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn type_args(args: Cli) -> TypeArgs {
+        match args.command {
+            Command::Type(type_args) => type_args,
+            _ => panic!("type subcommand not parsed"),
+        }
+    }
+
     #[test]
     fn test_cli_defaults() {
-        let arglist = vec!["mhrs", "testdata/mwgfour.csv", "testdata/mwgfour-p1p3.bam"];
-        let args = Cli::parse_from(arglist);
+        let arglist = vec![
+            "mhrs",
+            "type",
+            "testdata/mwgfour.csv",
+            "testdata/mwgfour-p1p3.bam",
+        ];
+        let args = type_args(Cli::parse_with_legacy(arglist));
         assert_eq!(args.csv, PathBuf::from("testdata/mwgfour.csv"));
         assert_eq!(args.bam, PathBuf::from("testdata/mwgfour-p1p3.bam"));
-        assert_eq!(args.detection_threshold, 10);
-        assert_eq!(args.analytical_threshold, 0.04);
-        assert!(args.threshold_csv.is_none());
+        assert!(args.sample.is_none());
+        assert!(args.split_by.is_none());
+        assert_eq!(args.typing.caller, CallerKind::Threshold);
+        assert_eq!(args.typing.detection_threshold, 10);
+        assert_eq!(args.typing.analytical_threshold, 0.04);
+        assert_eq!(args.typing.rounding, ThresholdRounding::Exact);
+        assert!(args.typing.neighbor_ratio.is_none());
+        assert!(args.typing.threshold_csv.is_none());
+        assert_eq!(args.typing.threads, 1);
+        assert_eq!(args.typing.min_mapq, 0);
+        assert!(args.typing.reference.is_none());
+        assert!(!args.typing.paired);
+        assert!(args.typing.contig_aliases.is_none());
+        assert!(!args.typing.strict_contigs);
+        assert_eq!(args.typing.min_coverage, 20);
+        assert_eq!(args.typing.max_alleles, 2);
+    }
+
+    #[test]
+    fn test_cli_legacy_type() {
+        let arglist = vec![
+            "mhrs",
+            "--name",
+            "Item2",
+            "testdata/mwgfour.csv",
+            "testdata/mwgfour-p1p3.bam",
+            "-s",
+            "5",
+        ];
+        let args = type_args(Cli::parse_with_legacy(arglist));
+        assert_eq!(args.csv, PathBuf::from("testdata/mwgfour.csv"));
+        assert_eq!(args.sample.as_deref(), Some("Item2"));
+        assert_eq!(args.typing.detection_threshold, 5);
+    }
+
+    #[test]
+    fn test_cli_split_by() {
+        let arglist = vec![
+            "mhrs",
+            "testdata/mwgfour.csv",
+            "testdata/mwgfour-multiplex.bam",
+            "--split-by",
+            "sample",
+        ];
+        let args = type_args(Cli::parse_with_legacy(arglist));
+        assert_eq!(args.split_by, Some(SplitBy::Sample));
+        let arglist = vec![
+            "mhrs",
+            "type",
+            "testdata/mwgfour.csv",
+            "testdata/mwgfour-multiplex.bam",
+            "--split-by",
+            "read-group",
+            "--name",
+            "Item1",
+        ];
+        assert!(Cli::try_parse_from(arglist).is_err());
+    }
+
+    #[test]
+    fn test_cli_batch() {
+        let arglist = vec![
+            "mhrs",
+            "batch",
+            "testdata/mwgfour.csv",
+            "testdata/mwgfour-samplesheet.csv",
+            "--combined",
+            "run.json",
+            "-s",
+            "5",
+        ];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Batch(batch) => {
+                assert_eq!(
+                    batch.samplesheet,
+                    PathBuf::from("testdata/mwgfour-samplesheet.csv")
+                );
+                assert_eq!(batch.outdir, PathBuf::from("."));
+                assert_eq!(batch.combined, Some(PathBuf::from("run.json")));
+                assert_eq!(batch.typing.detection_threshold, 5);
+            }
+            _ => panic!("batch subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_export() {
+        let arglist = vec![
+            "mhrs",
+            "export",
+            "testdata/mwgfour-p2-profile.json",
+            "--layout",
+            "wide",
+            "-f",
+            "tsv",
+        ];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Export(export) => {
+                assert_eq!(export.profiles.len(), 1);
+                assert_eq!(export.layout, TableLayout::Wide);
+                assert_eq!(export.format, TableFormat::Tsv);
+                assert!(export.out.is_none());
+            }
+            _ => panic!("export subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_vcf() {
+        let arglist = vec![
+            "mhrs", "vcf", "defn.csv", "p1.json", "p2.json", "-o", "run.vcf",
+        ];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Vcf(vcf) => {
+                assert_eq!(vcf.csv, PathBuf::from("defn.csv"));
+                assert_eq!(vcf.profiles.len(), 2);
+                assert_eq!(vcf.out, Some(PathBuf::from("run.vcf")));
+            }
+            _ => panic!("vcf subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_mixture() {
+        let arglist = vec!["mhrs", "mixture", "mix.json", "--known", "victim.json"];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Mixture(mixture) => {
+                assert_eq!(mixture.profile, PathBuf::from("mix.json"));
+                assert_eq!(mixture.known, Some(PathBuf::from("victim.json")));
+            }
+            _ => panic!("mixture subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_rmp() {
+        let arglist = vec!["mhrs", "rmp", "p.json", "f.csv", "-P", "EUR", "-P", "AFR"];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Rmp(rmp) => {
+                assert_eq!(rmp.frequencies, PathBuf::from("f.csv"));
+                assert_eq!(rmp.populations, vec!["EUR", "AFR"]);
+                assert_eq!(rmp.theta, 0.01);
+                assert_eq!(rmp.min_frequency, 0.001);
+            }
+            _ => panic!("rmp subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_kinship() {
+        let arglist = vec!["mhrs", "kinship", "a.json", "b.json", "f.csv", "-P", "EUR"];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::Kinship(kinship) => {
+                assert_eq!(kinship.second, PathBuf::from("b.json"));
+                assert_eq!(kinship.population, "EUR");
+                assert_eq!(kinship.allowance, 0.001);
+            }
+            _ => panic!("kinship subcommand not parsed"),
+        }
+    }
+
+    #[test]
+    fn test_cli_validate_panel() {
+        let arglist = vec!["mhrs", "validate-panel", "testdata/mwgfour.csv"];
+        match Cli::parse_with_legacy(arglist).command {
+            Command::ValidatePanel(validate) => {
+                assert_eq!(validate.csv, PathBuf::from("testdata/mwgfour.csv"));
+                assert!(validate.reference.is_none());
+            }
+            _ => panic!("validate-panel subcommand not parsed"),
+        }
     }
 }
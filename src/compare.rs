@@ -0,0 +1,141 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+use crate::profile::MicrohapProfile;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Outcome of comparing the genotype calls of two profiles at a single microhap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Concordance {
+    Match,
+    Mismatch,
+    MissingFirst,
+    MissingSecond,
+}
+
+impl fmt::Display for Concordance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Concordance::Match => "match",
+            Concordance::Mismatch => "mismatch",
+            Concordance::MissingFirst => "missing_first",
+            Concordance::MissingSecond => "missing_second",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Marker-by-marker comparison of the genotype calls in two profiles, such as replicate typing of a
+/// single sample or a questioned sample and a reference sample. Genotypes are compared as sets of
+/// alleles; read counts are ignored.
+pub struct ProfileComparison {
+    samples: (String, String),
+    markers: BTreeMap<String, (String, String, Concordance)>,
+}
+
+impl ProfileComparison {
+    /// Compare the genotype call at every microhap typed in either profile.
+    pub fn new(first: &MicrohapProfile, second: &MicrohapProfile) -> ProfileComparison {
+        let mut markers = BTreeMap::new();
+        for (mhid, result) in first.iter() {
+            let outcome = match second.get(mhid) {
+                None => Concordance::MissingSecond,
+                Some(other) => {
+                    let mut alleles1 = result.genotype.clone();
+                    let mut alleles2 = other.genotype.clone();
+                    alleles1.sort();
+                    alleles2.sort();
+                    match alleles1 == alleles2 {
+                        true => Concordance::Match,
+                        false => Concordance::Mismatch,
+                    }
+                }
+            };
+            let entry = (result.genotype_string(), String::new(), outcome);
+            markers.insert(mhid.clone(), entry);
+        }
+        for (mhid, result) in second.iter() {
+            let entry = markers
+                .entry(mhid.clone())
+                .or_insert_with(|| (String::new(), String::new(), Concordance::MissingFirst));
+            entry.1 = result.genotype_string();
+        }
+        ProfileComparison {
+            samples: (
+                first.sample_id().to_string(),
+                second.sample_id().to_string(),
+            ),
+            markers,
+        }
+    }
+
+    /// Number of markers with the given outcome.
+    pub fn count(&self, concordance: Concordance) -> usize {
+        self.markers
+            .values()
+            .filter(|(_, _, outcome)| *outcome == concordance)
+            .count()
+    }
+
+    /// Tab-separated table with one row per marker: the genotype call in each profile and the
+    /// outcome of the comparison.
+    pub fn to_table(&self) -> String {
+        let mut table = format!("Marker\t{}\t{}\tResult\n", self.samples.0, self.samples.1);
+        for (mhid, (first, second, outcome)) in &self.markers {
+            table.push_str(&format!("{}\t{}\t{}\t{}\n", mhid, first, second, outcome));
+        }
+        table
+    }
+
+    /// One-line summary of the number of matching genotypes among markers typed in both profiles.
+    pub fn summary(&self) -> String {
+        let matches = self.count(Concordance::Match);
+        let compared = matches + self.count(Concordance::Mismatch);
+        let missing =
+            self.count(Concordance::MissingFirst) + self.count(Concordance::MissingSecond);
+        format!(
+            "{} of {} shared markers concordant; {} markers typed in only one profile",
+            matches, compared, missing
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_compare_self() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let comparison = ProfileComparison::new(&profile, &profile);
+        assert_eq!(comparison.count(Concordance::Match), 4);
+        assert_eq!(comparison.count(Concordance::Mismatch), 0);
+        assert_eq!(
+            comparison.summary(),
+            "4 of 4 shared markers concordant; 0 markers typed in only one profile"
+        );
+    }
+
+    #[test]
+    fn test_compare_different_samples() {
+        let p1 = MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1-profile.json")).unwrap();
+        let p2 = MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let comparison = ProfileComparison::new(&p1, &p2);
+        assert_eq!(comparison.count(Concordance::MissingFirst), 0);
+        assert_eq!(comparison.count(Concordance::MissingSecond), 0);
+        assert!(comparison.count(Concordance::Mismatch) > 0);
+        let table = comparison.to_table();
+        assert!(table.starts_with("Marker\tItem1\tItem2\tResult\n"));
+        assert!(table.contains("mh04WL-052.v1\t"));
+    }
+}
@@ -8,13 +8,16 @@
 // Development Center.
 // -------------------------------------------------------------------------------------------------
 
+use crate::read::ReadHaplotype;
 use std::collections::HashMap;
 
 /// Description of the allele-defining SNPs that are used to distinguish different haplotypes
-/// observed at a microhap locus.
+/// observed at a microhap locus, along with the reference allele if known.
 #[derive(Clone, Debug)]
 pub struct AlleleDefinition {
     pub chromosome: String,
+    /// Reference genome base at each ADS, in order; see `MicrohapPanel::load_reference`.
+    pub reference: Option<ReadHaplotype>,
     offsets: Vec<u32>,
     indices: HashMap<u32, usize>,
 }
@@ -24,6 +27,7 @@ impl AlleleDefinition {
     pub fn new(chromosome: &str) -> AlleleDefinition {
         AlleleDefinition {
             chromosome: chromosome.to_string(),
+            reference: None,
             offsets: Vec::new(),
             indices: HashMap::new(),
         }
@@ -49,6 +53,11 @@ impl AlleleDefinition {
         self.offsets.len()
     }
 
+    /// Indicate whether the same genomic coordinate was added more than once.
+    pub fn has_duplicate_snps(&self) -> bool {
+        self.indices.len() < self.offsets.len()
+    }
+
     /// Given a genomic coordinate, return the index of the corresponding SNP in this allele definition.
     pub fn get_index(&self, offset: u32) -> Option<&usize> {
         self.indices.get(&offset)
@@ -59,14 +68,19 @@ impl AlleleDefinition {
         self.indices.contains_key(&offset)
     }
 
+    /// Genomic coordinates of all SNPs in this allele definition, in sorted order.
+    pub fn get_offsets(&self) -> &Vec<u32> {
+        &self.offsets
+    }
+
     /// Genomic coordinate of the first SNP in this allele definition.
     pub fn start(&self) -> u32 {
-        self.offsets[0] as u32
+        self.offsets[0]
     }
 
     /// Genomic coordinate of the last SNP in this allele definition.
     pub fn end(&self) -> u32 {
-        self.offsets[self.offsets.len() - 1] as u32
+        self.offsets[self.offsets.len() - 1]
     }
 
     /// A 3-tuple indicating the absolute genomic position of this microhap allele, in the format (chrom:start-end).
@@ -89,6 +103,7 @@ mod tests {
                 .collect();
             AlleleDefinition {
                 chromosome: chromosome.to_string(),
+                reference: None,
                 offsets,
                 indices,
             }
@@ -104,10 +119,6 @@ mod tests {
                 }
             }
         }
-
-        pub fn get_offsets(&self) -> &Vec<u32> {
-            &self.offsets
-        }
     }
 
     #[test]
@@ -124,6 +135,9 @@ mod tests {
         let observed = def.get_offsets();
         let expected: Vec<u32> = vec![53008000, 53008025, 53008042, 53008078];
         assert_eq!(observed, &expected);
+        assert!(!def.has_duplicate_snps());
+        def.add_snp_offset(53008042);
+        assert!(def.has_duplicate_snps());
     }
 
     #[test]
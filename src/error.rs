@@ -0,0 +1,168 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate csv;
+extern crate serde_json;
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors that can occur while loading inputs, reading alignments, or writing outputs. Each variant
+/// carries enough context (file and, where applicable, line) to locate the problem.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A CSV file could not be parsed; `line` is the 1-based line of the offending record.
+    Csv {
+        path: PathBuf,
+        line: Option<u64>,
+        message: String,
+    },
+    /// Alignments could not be opened or read by htslib.
+    Bam { path: PathBuf, message: String },
+    /// A profile or typing result could not be parsed from JSON.
+    Json {
+        path: Option<PathBuf>,
+        source: serde_json::Error,
+    },
+    /// Inputs were read successfully but are inconsistent or unusable.
+    Validation(String),
+}
+
+/// Result type used throughout mhrs.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Error reading or writing the file at the given path.
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        }
+    }
+
+    /// Error parsing a record in the CSV file at the given path. The line number is taken from
+    /// the record's position, if available.
+    pub fn csv(path: &Path, position: Option<&csv::Position>, message: &str) -> Error {
+        Error::Csv {
+            path: path.to_path_buf(),
+            line: position.map(|pos| pos.line()),
+            message: message.to_string(),
+        }
+    }
+
+    /// Error reported by the CSV reader or writer for the file at the given path.
+    pub fn csv_file(path: &Path, error: csv::Error) -> Error {
+        match error.kind() {
+            csv::ErrorKind::Io(_) => Error::io(path, io::Error::from(error)),
+            _ => Error::csv(path, error.position(), &error.to_string()),
+        }
+    }
+
+    /// Error reported by htslib for the alignments at the given path.
+    pub fn bam(path: &Path, error: impl fmt::Display) -> Error {
+        Error::Bam {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        }
+    }
+
+    /// Process exit status for this kind of error. Status 1 is reserved for unexpected failures
+    /// and status 2 for command-line usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Io { .. } => 3,
+            Error::Csv { .. } => 4,
+            Error::Bam { .. } => 5,
+            Error::Json { .. } => 6,
+            Error::Validation(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "could not access {}: {}", path.display(), source),
+            Error::Csv {
+                path,
+                line: None,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            Error::Csv {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "{}, line {}: {}", path.display(), line, message),
+            Error::Bam { path, message } => write!(f, "{}: {}", path.display(), message),
+            Error::Json { path: None, source } => write!(f, "invalid JSON: {}", source),
+            Error::Json {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: invalid JSON: {}", path.display(), source),
+            Error::Validation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Error {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(source: serde_json::Error) -> Error {
+        Error::Json { path: None, source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let error = Error::csv(Path::new("panel.csv"), None, "missing coordinate");
+        assert_eq!(error.to_string(), "panel.csv: missing coordinate");
+        assert_eq!(error.exit_code(), 4);
+        let error = Error::Csv {
+            path: PathBuf::from("panel.csv"),
+            line: Some(7),
+            message: "invalid coordinate 'abc'".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "panel.csv, line 7: invalid coordinate 'abc'"
+        );
+        let error = Error::io(Path::new("x.json"), io::Error::other("boom"));
+        assert_eq!(error.to_string(), "could not access x.json: boom");
+        assert_eq!(error.exit_code(), 3);
+    }
+}
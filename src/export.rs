@@ -0,0 +1,228 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate csv;
+
+use crate::error::{Error, Result};
+use crate::profile::MicrohapProfile;
+use clap::ValueEnum;
+use csv::WriterBuilder;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::Path;
+
+/// Arrangement of the exported table.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TableLayout {
+    /// One row per sample, marker, and observed allele
+    Long,
+    /// One row per sample, with one genotype column per marker
+    Wide,
+}
+
+/// Field delimiter of the exported table.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+}
+
+impl TableFormat {
+    fn delimiter(&self) -> u8 {
+        match self {
+            TableFormat::Csv => b',',
+            TableFormat::Tsv => b'\t',
+        }
+    }
+}
+
+/// Data structure for flattening one or more microhap profiles into a table for spreadsheets and
+/// laboratory information management systems.
+///
+/// In the long layout, each allele with a non-zero read count is reported on its own row, along
+/// with whether it was included in the genotype call, its frequency relative to all complete read
+/// haplotypes observed at the marker, and the thresholds applied to the marker. In the wide layout,
+/// each sample is reported on a single row, with the genotype call for each marker in its own
+/// column as a slash-separated list of alleles.
+pub struct ProfileExporter {
+    profiles: Vec<MicrohapProfile>,
+}
+
+impl ProfileExporter {
+    /// Prepare the given profiles for export, in order.
+    pub fn new(profiles: Vec<MicrohapProfile>) -> ProfileExporter {
+        ProfileExporter { profiles }
+    }
+
+    /// Write the profiles as a table with the specified layout and delimiter. Errors are reported
+    /// against `destination`, the path of the file underlying `writer`.
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        destination: &Path,
+        layout: TableLayout,
+        format: TableFormat,
+    ) -> Result<()> {
+        let mut table = WriterBuilder::new()
+            .delimiter(format.delimiter())
+            .from_writer(writer);
+        let outcome = match layout {
+            TableLayout::Long => self.write_long(&mut table),
+            TableLayout::Wide => self.write_wide(&mut table),
+        };
+        outcome
+            .and_then(|_| table.flush().map_err(csv::Error::from))
+            .map_err(|e| Error::csv_file(destination, e))
+    }
+
+    fn write_long<W: Write>(&self, table: &mut csv::Writer<W>) -> csv::Result<()> {
+        table.write_record([
+            "Sample",
+            "Marker",
+            "Allele",
+            "Genotype",
+            "ReadCount",
+            "Frequency",
+            "Detection",
+            "Dynamic",
+            "Analytical",
+        ])?;
+        for profile in &self.profiles {
+            for (mhid, result) in profile.iter() {
+                let total: usize = result.counts.tally.values().sum();
+                let mut alleles: Vec<_> = result.counts.tally.iter().collect();
+                alleles.sort();
+                for (allele, count) in alleles {
+                    let frequency = *count as f64 / total as f64;
+                    let called = result.genotype.contains(allele);
+                    table.write_record([
+                        profile.sample_id().to_string(),
+                        mhid.to_string(),
+                        allele.to_string(),
+                        called.to_string(),
+                        count.to_string(),
+                        format!("{:.4}", frequency),
+                        result.thresholds.detection.to_string(),
+                        result.thresholds.dynamic.to_string(),
+                        result.thresholds.analytical.to_string(),
+                    ])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_wide<W: Write>(&self, table: &mut csv::Writer<W>) -> csv::Result<()> {
+        let markers: BTreeSet<&String> = self
+            .profiles
+            .iter()
+            .flat_map(|profile| profile.iter().map(|(mhid, _)| mhid))
+            .collect();
+        let mut header = vec!["Sample"];
+        header.extend(markers.iter().map(|mhid| mhid.as_str()));
+        table.write_record(&header)?;
+        for profile in &self.profiles {
+            let mut record = vec![profile.sample_id().to_string()];
+            for mhid in &markers {
+                let genotype = match profile.get(mhid) {
+                    Some(result) => result.genotype_string(),
+                    None => String::new(),
+                };
+                record.push(genotype);
+            }
+            table.write_record(&record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn load_exporter() -> ProfileExporter {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        ProfileExporter::new(vec![profile])
+    }
+
+    #[test]
+    fn test_export_long_csv() {
+        let exporter = load_exporter();
+        let mut output = Vec::new();
+        exporter
+            .write(
+                &mut output,
+                Path::new("-"),
+                TableLayout::Long,
+                TableFormat::Csv,
+            )
+            .unwrap();
+        let table = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Sample,Marker,Allele,Genotype,ReadCount,Frequency,Detection,Dynamic,Analytical"
+        );
+        assert!(lines.contains(&"Item2,mh03USC-3qC.v2,CCACTGG,true,1151,0.4974,10,0.04,92.08"));
+        assert!(lines.contains(&"Item2,mh03USC-3qC.v2,CCACTGA,false,1,0.0004,10,0.04,92.08"));
+    }
+
+    #[test]
+    fn test_export_wide_tsv() {
+        let exporter = load_exporter();
+        let mut output = Vec::new();
+        exporter
+            .write(
+                &mut output,
+                Path::new("-"),
+                TableLayout::Wide,
+                TableFormat::Tsv,
+            )
+            .unwrap();
+        let table = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "Sample\tmh03USC-3qC.v2\tmh04WL-052.v1\tmh06SCUZJ-0528857\tmh17FHL-005.v3"
+        );
+        assert!(lines[1].starts_with("Item2\tCCACTGG/CTACTGG\tACCGGGCTC/GCCAAGCTC\t"));
+    }
+
+    #[test]
+    fn test_export_write_error() {
+        let exporter = load_exporter();
+        let error = exporter
+            .write(
+                FailingWriter,
+                Path::new("table.csv"),
+                TableLayout::Wide,
+                TableFormat::Csv,
+            )
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "could not access table.csv: disk full");
+        assert_eq!(error.exit_code(), 3);
+    }
+}
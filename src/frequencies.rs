@@ -0,0 +1,119 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate csv;
+
+use crate::error::{Error, Result};
+use crate::read::ReadHaplotype;
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Allele frequencies of a single microhap in a single population.
+type MarkerFrequencies = HashMap<ReadHaplotype, f64>;
+
+/// Population allele frequencies for the microhaps in a panel, loaded from a CSV file with one row
+/// per marker, allele, and population. See Appendix C of the user guide for the expected format.
+pub struct FrequencyTable {
+    populations: BTreeMap<String, HashMap<String, MarkerFrequencies>>,
+}
+
+impl FrequencyTable {
+    /// Load allele frequencies from a CSV file with a header row and four columns: marker name,
+    /// allele, population, and frequency.
+    pub fn from_csv(csv_path: &Path) -> Result<FrequencyTable> {
+        let mut reader = ReaderBuilder::new()
+            .from_path(csv_path)
+            .map_err(|e| Error::csv_file(csv_path, e))?;
+        let mut populations: BTreeMap<String, HashMap<String, MarkerFrequencies>> = BTreeMap::new();
+        for result in reader.records() {
+            let record = result.map_err(|e| Error::csv_file(csv_path, e))?;
+            let position = record.position();
+            let (marker, allele, population, frequency) =
+                match (record.get(0), record.get(1), record.get(2), record.get(3)) {
+                    (Some(marker), Some(allele), Some(population), Some(frequency)) => {
+                        (marker, allele, population, frequency)
+                    }
+                    _ => return Err(Error::csv(csv_path, position, "expected 4 fields")),
+                };
+            let frequency = frequency
+                .parse::<f64>()
+                .ok()
+                .filter(|f| *f > 0.0 && *f <= 1.0)
+                .ok_or_else(|| {
+                    let message = format!("invalid allele frequency '{}'", frequency);
+                    Error::csv(csv_path, position, &message)
+                })?;
+            populations
+                .entry(population.to_string())
+                .or_default()
+                .entry(marker.to_string())
+                .or_default()
+                .insert(ReadHaplotype::from_string(allele), frequency);
+        }
+        Ok(FrequencyTable { populations })
+    }
+
+    /// Names of the populations in the table, sorted.
+    pub fn populations(&self) -> impl Iterator<Item = &String> {
+        self.populations.keys()
+    }
+
+    /// Indicate whether the table includes the given population.
+    pub fn has_population(&self, population: &str) -> bool {
+        self.populations.contains_key(population)
+    }
+
+    /// Indicate whether the table includes frequencies for the marker in the given population.
+    pub fn has_marker(&self, population: &str, marker: &str) -> bool {
+        self.populations
+            .get(population)
+            .is_some_and(|markers| markers.contains_key(marker))
+    }
+
+    /// Frequency of the given allele in the given population, if the allele has been observed.
+    pub fn get(&self, population: &str, marker: &str, allele: &ReadHaplotype) -> Option<f64> {
+        self.populations
+            .get(population)
+            .and_then(|markers| markers.get(marker))
+            .and_then(|alleles| alleles.get(allele))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequencies_csv() {
+        let table =
+            FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies.csv")).unwrap();
+        let populations: Vec<&String> = table.populations().collect();
+        assert_eq!(populations, vec!["AFR", "EUR"]);
+        let allele = ReadHaplotype::from_string("CCACTGT");
+        assert_eq!(table.get("EUR", "mh03USC-3qC.v2", &allele), Some(0.4));
+        assert_eq!(table.get("AFR", "mh03USC-3qC.v2", &allele), Some(0.5));
+        assert_eq!(table.get("AFR", "mh04WL-052.v1", &allele), None);
+        assert!(table.has_marker("EUR", "mh17FHL-005.v3"));
+        assert!(!table.has_marker("AFR", "mh17FHL-005.v3"));
+        assert!(!table.has_population("ASN"));
+    }
+
+    #[test]
+    fn test_frequencies_csv_invalid() {
+        let result = FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies-bad.csv"));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/mwgfour-frequencies-bad.csv, line 3: invalid allele frequency '1.5'"
+        );
+    }
+}
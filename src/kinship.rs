@@ -0,0 +1,395 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+use crate::error::{Error, Result};
+use crate::frequencies::FrequencyTable;
+use crate::profile::MicrohapProfile;
+use crate::read::ReadHaplotype;
+use crate::rmp::{allele_frequency, validate_min_frequency};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Relationship between two individuals, characterized by the probabilities that they share zero,
+/// one, or two alleles identical by descent (IBD) at a locus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Relationship {
+    ParentChild,
+    FullSibling,
+    HalfSibling,
+    Unrelated,
+}
+
+impl Relationship {
+    /// All relationships, in the order reported.
+    pub const ALL: [Relationship; 4] = [
+        Relationship::ParentChild,
+        Relationship::FullSibling,
+        Relationship::HalfSibling,
+        Relationship::Unrelated,
+    ];
+
+    /// Probabilities (k0, k1, k2) of sharing zero, one, or two alleles IBD.
+    pub fn ibd(&self) -> (f64, f64, f64) {
+        match self {
+            Relationship::ParentChild => (0.0, 1.0, 0.0),
+            Relationship::FullSibling => (0.25, 0.5, 0.25),
+            Relationship::HalfSibling => (0.5, 0.5, 0.0),
+            Relationship::Unrelated => (1.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl fmt::Display for Relationship {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Relationship::ParentChild => "parent_child",
+            Relationship::FullSibling => "full_sibling",
+            Relationship::HalfSibling => "half_sibling",
+            Relationship::Unrelated => "unrelated",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Parameters for computing kinship likelihood ratios.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KinshipModel {
+    /// Probability that an allele shared IBD is not observed as such, due to mutation or allelic
+    /// dropout; the allele observed instead is drawn at random from the population.
+    pub allowance: f64,
+    /// Frequency assumed for alleles absent from the frequency table or rarer than this minimum;
+    /// no theta correction is made.
+    pub min_frequency: f64,
+}
+
+impl Default for KinshipModel {
+    fn default() -> KinshipModel {
+        KinshipModel {
+            allowance: 0.001,
+            min_frequency: 0.001,
+        }
+    }
+}
+
+impl KinshipModel {
+    /// Initialize with the given mutation/dropout allowance, which must be in [0, 1], and minimum
+    /// allele frequency, which must be in (0, 1].
+    pub fn new(allowance: f64, min_frequency: f64) -> Result<KinshipModel> {
+        let model = KinshipModel {
+            allowance,
+            min_frequency,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Check that the allowance and the minimum allele frequency are within range.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.allowance) {
+            let message = format!("allowance {} outside the range [0, 1]", self.allowance);
+            return Err(Error::Validation(message));
+        }
+        validate_min_frequency(self.min_frequency)
+    }
+
+    /// Likelihood ratio of the given relationship versus unrelated individuals, given the
+    /// genotypes of both individuals (one allele for a homozygote, two for a heterozygote) and the
+    /// population frequency of each allele.
+    pub fn likelihood_ratio<F>(
+        &self,
+        relationship: Relationship,
+        first: &[ReadHaplotype],
+        second: &[ReadHaplotype],
+        frequency: F,
+    ) -> f64
+    where
+        F: Fn(&ReadHaplotype) -> f64,
+    {
+        let (k0, k1, k2) = relationship.ibd();
+        let first: Vec<&ReadHaplotype> = diploid(first);
+        let second: Vec<&ReadHaplotype> = diploid(second);
+        let transmitted = |allele: &ReadHaplotype| {
+            let copies = first.iter().filter(|a| **a == allele).count() as f64;
+            (1.0 - self.allowance) * copies / 2.0 + self.allowance * frequency(allele)
+        };
+        let (c, d) = (second[0], second[1]);
+        let (unrelated, ibd1) = match c == d {
+            true => (frequency(c).powi(2), transmitted(c) * frequency(c)),
+            false => (
+                2.0 * frequency(c) * frequency(d),
+                transmitted(c) * frequency(d) + transmitted(d) * frequency(c),
+            ),
+        };
+        let identical = match first == second {
+            true => 1.0,
+            false => 0.0,
+        };
+        let ibd2 = (1.0 - self.allowance) * identical + self.allowance * ibd1;
+        k0 + k1 * ibd1 / unrelated + k2 * ibd2 / unrelated
+    }
+}
+
+/// Both alleles of a genotype, sorted, with the single allele of a homozygote repeated.
+fn diploid(genotype: &[ReadHaplotype]) -> Vec<&ReadHaplotype> {
+    let mut alleles: Vec<&ReadHaplotype> = genotype.iter().collect();
+    if alleles.len() == 1 {
+        alleles.push(alleles[0]);
+    }
+    alleles.sort();
+    alleles
+}
+
+/// Per-marker and combined likelihood ratios for the relationship between the individuals typed
+/// in two single-source profiles, relative to the hypothesis that they are unrelated. Markers
+/// typed in only one profile, absent from the frequency table, or with no alleles or more than two
+/// alleles called in either profile are skipped.
+pub struct KinshipTest {
+    samples: (String, String),
+    markers: BTreeMap<String, (String, String, Vec<f64>)>,
+    skipped: Vec<String>,
+}
+
+impl KinshipTest {
+    /// Compute likelihood ratios for every relationship using allele frequencies from the given
+    /// population. Fails if the model parameters are out of range.
+    pub fn new(
+        first: &MicrohapProfile,
+        second: &MicrohapProfile,
+        table: &FrequencyTable,
+        population: &str,
+        model: &KinshipModel,
+    ) -> Result<KinshipTest> {
+        model.validate()?;
+        if !table.has_population(population) {
+            let message = format!("population {} absent from the frequency table", population);
+            return Err(Error::Validation(message));
+        }
+        let mut markers = BTreeMap::new();
+        let mut skipped = Vec::new();
+        for (mhid, result1) in first.iter() {
+            let result2 = match second.get(mhid) {
+                Some(result2) => result2,
+                None => {
+                    skipped.push(mhid.clone());
+                    continue;
+                }
+            };
+            let typed = |genotype: &Vec<ReadHaplotype>| (1..=2).contains(&genotype.len());
+            if !typed(&result1.genotype)
+                || !typed(&result2.genotype)
+                || !table.has_marker(population, mhid)
+            {
+                skipped.push(mhid.clone());
+                continue;
+            }
+            let frequency = |allele: &ReadHaplotype| {
+                allele_frequency(table, population, mhid, allele, model.min_frequency)
+            };
+            let ratios = Relationship::ALL
+                .iter()
+                .map(|relationship| {
+                    model.likelihood_ratio(
+                        *relationship,
+                        &result1.genotype,
+                        &result2.genotype,
+                        frequency,
+                    )
+                })
+                .collect();
+            let entry = (result1.genotype_string(), result2.genotype_string(), ratios);
+            markers.insert(mhid.clone(), entry);
+        }
+        skipped.extend(
+            second
+                .iter()
+                .map(|(mhid, _)| mhid)
+                .filter(|mhid| first.get(mhid).is_none())
+                .cloned(),
+        );
+        Ok(KinshipTest {
+            samples: (
+                first.sample_id().to_string(),
+                second.sample_id().to_string(),
+            ),
+            markers,
+            skipped,
+        })
+    }
+
+    /// Likelihood ratio of the given relationship at the given marker, if the marker was tested.
+    pub fn likelihood_ratio(&self, marker: &str, relationship: Relationship) -> Option<f64> {
+        let index = Relationship::ALL.iter().position(|r| *r == relationship)?;
+        self.markers.get(marker).map(|(_, _, ratios)| ratios[index])
+    }
+
+    /// Combined likelihood ratio of the given relationship: the product over all tested markers.
+    pub fn combined(&self, relationship: Relationship) -> f64 {
+        let index = Relationship::ALL
+            .iter()
+            .position(|r| *r == relationship)
+            .unwrap();
+        let log10_lr: f64 = self
+            .markers
+            .values()
+            .map(|(_, _, ratios)| ratios[index].log10())
+            .sum();
+        10f64.powf(log10_lr)
+    }
+
+    /// Markers that could not be tested.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Tab-separated table with one row per tested marker, giving the genotype in each profile and
+    /// the likelihood ratio of each relationship, followed by a row with the combined ratios.
+    pub fn to_table(&self) -> String {
+        let relationships: Vec<String> = Relationship::ALL.iter().map(|r| r.to_string()).collect();
+        let mut table = format!(
+            "Marker\t{}\t{}\t{}\n",
+            self.samples.0,
+            self.samples.1,
+            relationships.join("\t")
+        );
+        for (mhid, (genotype1, genotype2, ratios)) in &self.markers {
+            let ratios: Vec<String> = ratios.iter().map(|lr| format!("{:.4e}", lr)).collect();
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                mhid,
+                genotype1,
+                genotype2,
+                ratios.join("\t")
+            ));
+        }
+        let combined: Vec<String> = Relationship::ALL
+            .iter()
+            .map(|r| format!("{:.4e}", self.combined(*r)))
+            .collect();
+        table.push_str(&format!("Combined\t\t\t{}\n", combined.join("\t")));
+        table
+    }
+
+    /// Summary of the combined likelihood ratio of each relationship, one line per relationship.
+    pub fn summary(&self) -> String {
+        Relationship::ALL
+            .iter()
+            .filter(|r| **r != Relationship::Unrelated)
+            .map(|r| {
+                format!(
+                    "{} vs unrelated: LR {:.4e} over {} markers",
+                    r,
+                    self.combined(*r),
+                    self.markers.len()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn genotype(alleles: &[&str]) -> Vec<ReadHaplotype> {
+        alleles
+            .iter()
+            .map(|a| ReadHaplotype::from_string(a))
+            .collect()
+    }
+
+    fn frequency(allele: &ReadHaplotype) -> f64 {
+        match allele.to_string().as_str() {
+            "A" => 0.3,
+            "B" => 0.2,
+            _ => 0.1,
+        }
+    }
+
+    #[test]
+    fn test_kinship_likelihood_ratio() {
+        let model = KinshipModel {
+            allowance: 0.0,
+            ..KinshipModel::default()
+        };
+        let ab = genotype(&["A", "B"]);
+        let lr = model.likelihood_ratio(Relationship::ParentChild, &ab, &ab, frequency);
+        assert!((lr - 0.5 / 0.24).abs() < 1e-12);
+        let lr = model.likelihood_ratio(Relationship::FullSibling, &ab, &ab, frequency);
+        assert!((lr - 3.375).abs() < 1e-12);
+        let lr = model.likelihood_ratio(Relationship::HalfSibling, &ab, &ab, frequency);
+        assert!((lr - (0.5 + 0.5 * 0.5 / 0.24)).abs() < 1e-12);
+        let lr = model.likelihood_ratio(Relationship::Unrelated, &ab, &ab, frequency);
+        assert_eq!(lr, 1.0);
+
+        let aa = genotype(&["A"]);
+        let lr = model.likelihood_ratio(Relationship::ParentChild, &ab, &aa, frequency);
+        assert!((lr - 0.5 / 0.3).abs() < 1e-12);
+        let cd = genotype(&["C", "D"]);
+        let lr = model.likelihood_ratio(Relationship::ParentChild, &ab, &cd, frequency);
+        assert_eq!(lr, 0.0);
+
+        let model = KinshipModel::default();
+        let lr = model.likelihood_ratio(Relationship::ParentChild, &ab, &cd, frequency);
+        assert!(lr > 0.0 && lr < 0.01);
+    }
+
+    #[test]
+    fn test_kinship_model_range() {
+        assert!(KinshipModel::new(0.0, 1.0).is_ok());
+        assert!(KinshipModel::new(1.0, 0.001).is_ok());
+        let message = KinshipModel::new(2.0, 0.001).err().unwrap().to_string();
+        assert_eq!(message, "allowance 2 outside the range [0, 1]");
+        assert!(KinshipModel::new(-0.1, 0.001).is_err());
+        let message = KinshipModel::new(0.001, 0.0).err().unwrap().to_string();
+        assert_eq!(message, "minimum frequency 0 outside the range (0, 1]");
+
+        let p1 = MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1-profile.json")).unwrap();
+        let table =
+            FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies.csv")).unwrap();
+        let model = KinshipModel {
+            allowance: 2.0,
+            ..KinshipModel::default()
+        };
+        let result = KinshipTest::new(&p1, &p1, &table, "EUR", &model);
+        assert!(matches!(result, Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_kinship_profiles() {
+        let p1 = MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1-profile.json")).unwrap();
+        let p2 = MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let table =
+            FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies.csv")).unwrap();
+        let model = KinshipModel::default();
+        let kinship = KinshipTest::new(&p1, &p1, &table, "EUR", &model).unwrap();
+        assert!(kinship.combined(Relationship::ParentChild) > 1.0);
+        assert!(kinship.combined(Relationship::FullSibling) > 1.0);
+        assert_eq!(kinship.combined(Relationship::Unrelated), 1.0);
+        assert!(kinship.skipped().is_empty());
+
+        let kinship = KinshipTest::new(&p1, &p2, &table, "AFR", &model).unwrap();
+        assert!(kinship.combined(Relationship::ParentChild) < 1e-6);
+        assert_eq!(kinship.skipped(), ["mh17FHL-005.v3"]);
+        let lr = kinship.likelihood_ratio("mh03USC-3qC.v2", Relationship::HalfSibling);
+        assert!(lr.unwrap() > 0.0);
+        let table = kinship.to_table();
+        let header = "Marker\tItem1\tItem2\tparent_child\tfull_sibling\thalf_sibling\tunrelated\n";
+        assert!(table.starts_with(header));
+        assert!(table.contains("\nCombined\t\t\t"));
+
+        let table =
+            FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies.csv")).unwrap();
+        let result = KinshipTest::new(&p1, &p2, &table, "ASN", &model);
+        let message = result.err().unwrap().to_string();
+        assert_eq!(message, "population ASN absent from the frequency table");
+    }
+}
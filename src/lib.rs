@@ -0,0 +1,435 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+//! ## mhrs: an empirical microhaplotype calling algorithm written in Rust
+//!
+//! ### Quick reference for the impatient
+//!
+//! Provided for convenience if this isn't your first rodeo:
+//!
+//! `mhrs type defn.csv aligns.bam --static=12 --dynamic=0.025 > profile.json`
+//!
+//! The `type` subcommand is the default, so `mhrs defn.csv aligns.bam ...` works too. Other
+//! workflows are provided as subcommands: `batch` (typing every sample in a sample sheet), `export`
+//! (profiles as CSV/TSV tables), `vcf` (genotype calls in VCF format), `compare` (genotype
+//! concordance between two profiles), `mixture` (mixture proportion of a two-person mixture), `rmp`
+//! (random match probability), `kinship` (relationship testing), and `validate-panel` (checks on
+//! allele definitions). Run `mhrs help <subcommand>` for details.
+//!
+//! Otherwise, read on for more details.
+//!
+//! ### User guide
+//!
+//! This program is designed to genotype specific microhaplotype (*microhap* or *MH*) markers using
+//! NGS (e.g. Illumina) reads. It's theoretically possible that the reads could come from a
+//! whole-genome shotgun sequencing strategy, but the coverage needed for confident genotype calls
+//! will typically require targeting the desired regions with something like multiplex PCR
+//! amplification or hybridization capture enrichment. This program does not *discover* new MH loci,
+//! it is designed only to *genotype* pre-determined user-specified markers.
+//!
+//! To demonstrate how mhrs works, consider the microhap `mh16WL-040.v1` shown below. It is
+//! comprised of four SNPs spanning 24 bp on chromosome 16.
+//!
+//! ```text
+//!           *    **                *
+//! AGGCTTCAGGCGGCTACCCGTGAAATCCCAGGTGCTTACCACAA
+//! ```
+//!
+//! Given those SNP locations and a set of reads aligned to that location, mhrs begins by
+//! determining the haplotype observed for each individual read—in other words, the combination of
+//! alleles observed at the allele-defining SNPs (ADSs). All other positions are ignored, leaving
+//! (in this case) a 4 bp sequence as the "allele".
+//!
+//! ```text
+//! AGGCTTCAGGCGGCTACCCGTGAAATCCCAGGTGCTTACCACAA
+//! ..........T....CT..............
+//! ....G.....C....CC................G...
+//! ..........C....CC................G.......
+//! ..........C....CC................G..........
+//! ..........T....CT................C..........
+//! ..........C....CC................G..........
+//! ..........T....CT................C..........
+//! ..........T....AT................C..........
+//! ..........T....CT.......C........C..........
+//! ..........C....CC................G..........
+//!    .......T....CT................C..........
+//!       ....C....CC................G..........
+//!             ...CT................C..........
+//! ```
+//!
+//! In the example above, we see six reads with the `CCCG` allele, four reads with the `TCTC`
+//! allele, and one read with the `TATC` allele. The first and last reads are discarded since they
+//! do not fully span all four ADSs and therefore are unsuitable for empirical haplotype calling.
+//! Two reads appear to have sequencing errors at non-target positions, but this does not impact
+//! haplotype calling.
+//!
+//! Next, mhrs applies thresholds to distinguish false alleles (due e.g. to sequencing error) from
+//! true alleles. In the example above, the `TATC` allele is likely a false allele. First, a fixed
+//! *detection threshold* is applied to eliminate obvious low-level noise. Next, a dynamic
+//! *analytical threshold* is computed as a percentage of the total read counts for alleles not
+//! eliminated by the detection treshold. The alleles that remain after filters are applied
+//! constitute the genotype call for that microhap.
+//!
+//! Running the mhrs program requires 1) allele definitions (in CSV format) listing ADS coordinates
+//! for a panel of microhap targets and 2) read alignments (in BAM format) against the human genome.
+//! Alignments in CRAM format are also supported, provided the reference genome FASTA used for
+//! alignment is supplied with `--reference`. It is expected that paired-end reads are merged prior
+//! to alignment. Alternatively, unmerged read pairs can be typed with `--paired`, in which case the
+//! two mates of each pair are combined into a single fragment haplotype: where both mates cover an
+//! ADS and disagree, the base with the higher quality is used, and the fragment is discarded if the
+//! qualities are equal. The expected format of the allele definition CSV is shown in Appendix A
+//! below.
+//!
+//! Given a reference genome FASTA with `--reference`, whether or not the alignments are in CRAM
+//! format, mhrs looks up the reference base at each ADS. Each typing result then reports the
+//! `reference` haplotype, so that reference alleles and homozygous-reference genotypes can be
+//! identified. A warning is printed for each ADS where the reference base is N, and for each marker
+//! whose chromosome or ADS coordinates are absent from the reference (after applying any
+//! `--contig-aliases`); such markers are typed without a reference haplotype.
+//!
+//! Each profile is labeled with the sample name given by `--name`. If omitted, the name is taken
+//! from the `SM` field of the `@RG` lines in the alignment header or, if the header declares no
+//! sample, from the alignment file name without its extension (e.g. `Item1` for `Item1.bam`). If
+//! the header declares more than one sample, mhrs exits with an error; specify the sample with
+//! `--name`, or type each sample separately as described below.
+//!
+//! A BAM file containing several libraries or samples, distinguished by the `RG` tag of each read,
+//! can be typed with `--split-by read-group` or `--split-by sample`. All markers are observed in a
+//! single pass over the alignments, but separately for each read group declared in the `@RG`
+//! header lines, or for each sample (the `SM` field), pooling the read groups of the same sample.
+//! The resulting profiles, named by read group ID or sample, are printed as a JSON array; reads
+//! without a declared read group are ignored.
+//!
+//! The user can configure the filtering behavior of the mhrs program with panel-wide default
+//! thresholds, locus-specific thresholds (if validation studies have been performed), or a
+//! combination of both. Default thresholds can be configured using command-line arguments (run
+//! `mhrs type --help` for more information), while locus-specific thresholds can be provided in a
+//! CSV file, the expected format of which is shown in Appendix B below. Since the analytical
+//! threshold is a fraction of a read count, it is generally not a whole number; by default an
+//! allele must have at least that many reads (an allele with 92 reads fails a threshold of 92.08).
+//! Use `--threshold-rounding floor` to reproduce calls made by earlier versions of mhrs, which
+//! rounded the threshold down, or `--threshold-rounding round` to round it to the nearest whole
+//! number. The rule applied is recorded with each typing result's thresholds.
+//!
+//! As an alternative to fixed thresholds, `--caller likelihood` calls the most likely homozygous or
+//! heterozygous genotype given the base qualities of each read, considering as candidates only
+//! alleles that pass the detection threshold (at most the eight with the most reads). Typing
+//! results from the likelihood caller include a `likelihood` entry with the log10 likelihood of
+//! each candidate genotype and a Phred-scaled genotype quality (capped at 99). Being limited to two
+//! alleles, the likelihood caller is intended for single-source samples.
+//!
+//! Many false alleles result from a sequencing error at a single ADS in reads of an abundant true
+//! allele. With `--neighbor-ratio 0.05`, for example, an allele passing the thresholds is rejected
+//! if its read count is less than 5% of that of an allele differing from it at a single ADS. Each
+//! rejected allele is listed under `error_neighbors` along with the allele it is attributed to. The
+//! ratio must be strictly between 0 and 1.
+//!
+//! Each profile also includes a mixture assessment: the largest number of alleles called at any
+//! marker, the minimum number of contributors needed to explain it (two alleles per contributor),
+//! and the markers with more than two alleles called. The profile is flagged as a likely mixture if
+//! at least `--mixture-markers` markers (2 by default) have more than two alleles called.
+//!
+//! For a two-person mixture, `mhrs mixture profile.json` estimates the proportion of the major
+//! contributor from the read counts of the called alleles, along with an approximate 95% confidence
+//! interval, and reports the most likely genotypes of both contributors at each marker. If one
+//! contributor is known, such as a victim in a sexual assault case, `--known victim.json` fixes
+//! that contributor's genotypes, so that the proportion is that of the known contributor and the
+//! deconvolved genotypes are those of the unknown contributor.
+//!
+//! The random match probability (RMP) of a single-source profile is computed with `mhrs rmp
+//! profile.json frequencies.csv`, given population allele frequencies in the format shown in
+//! Appendix C below. Genotype frequencies are computed for each marker in each population (or only
+//! those given with `--population`) using NRC II recommendation 4.10, with a coancestry coefficient
+//! set by `--theta` (0.01 by default), and multiplied to give the combined RMP. Alleles absent from
+//! the frequency table, or rarer than `--min-frequency` (0.001 by default), are assigned the
+//! minimum frequency; NRC II recommends 5/2N for a database of N individuals. Markers with more
+//! than two alleles called are excluded.
+//!
+//! For relationship testing, `mhrs kinship first.json second.json frequencies.csv --population EUR`
+//! computes the likelihood ratio of a parent-child, full-sibling, or half-sibling relationship
+//! versus the hypothesis that the two individuals are unrelated, at each marker and combined over
+//! all markers. To allow for mutation and allelic dropout, an allele shared identical by descent is
+//! observed as a random allele from the population with probability `--allowance` (0.001 by
+//! default), so that a single inconsistent marker does not exclude a relationship outright.
+//!
+//! The program generates a *typing result* for each microhap in the panel comprised of the genotype
+//! call, per-base read coverage statistics, a breakdown of the reasons reads were rejected, and raw
+//! read counts (excluding partial observations for reads that don't span all ADSs). Each observed
+//! allele's read count is also reported as a frequency relative to the reads supporting called
+//! alleles (`passing`) and to all reads spanning the marker (`spanning`), and heterozygous calls
+//! report a heterozygote balance: the ratio of the minor to the major allele read count. The typing
+//! results for all microhaps in the panel constitute a *profile*, which is reported in JSON format.
+//! A profile containing a single typing result, based on the example above, is shown here.
+//!
+//! ```json
+//! {
+//!   "sample_id": "Sample001",
+//!   "mixture": {
+//!     "max_alleles": 2,
+//!     "min_contributors": 1,
+//!     "markers_over_two": [],
+//!     "likely_mixture": false
+//!   },
+//!   "typing_results": {
+//!     "mh16WL-040.v1": {
+//!       "status": "ok",
+//!       "genotype": [
+//!         "CCCG",
+//!         "TCTC",
+//!       ],
+//!       "coverage": {
+//!         "max": 11,
+//!         "mean": 10.568181818181818,
+//!         "min": 9
+//!       },
+//!       "num_discarded": 1,
+//!       "rejections": {
+//!         "flags": 0,
+//!         "mapping_quality": 0,
+//!         "base_quality": 0,
+//!         "gap": 0,
+//!         "partial_span": 1,
+//!         "depth_capped": 0,
+//!         "mate_conflict": 0
+//!       },
+//!       "thresholds": {
+//!         "dynamic": 0.02,
+//!         "analytical": 0.2,
+//!         "detection": 2,
+//!         "rounding": "exact"
+//!       },
+//!       "error_neighbors": {},
+//!       "het_balance": 0.6666666666666666,
+//!       "frequencies": {
+//!         "CCCG": {
+//!           "passing": 0.6,
+//!           "spanning": 0.5454545454545454
+//!         },
+//!         "TATC": {
+//!           "passing": 0.1,
+//!           "spanning": 0.09090909090909091
+//!         },
+//!         "TCTC": {
+//!           "passing": 0.4,
+//!           "spanning": 0.36363636363636365
+//!         }
+//!       },
+//!       "qc_flags": [],
+//!       "counts": {
+//!         "CCCG": 6,
+//!         "TATC": 1,
+//!         "TCTC": 4
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! For spreadsheets and laboratory information management systems, one or more profiles can be
+//! flattened into a CSV or TSV table with `mhrs export`. The long layout (the default) reports one
+//! row per sample, marker, and observed allele, including whether the allele was called, its read
+//! count and frequency, and the thresholds applied; the wide layout (`--layout wide`) reports one
+//! row per sample with the genotype call for each marker in its own column. Each profile file may
+//! hold a single profile or a JSON array of profiles, as written by `--split-by` or `batch
+//! --combined`; the same holds for `mhrs vcf` below.
+//!
+//! `mhrs export profile1.json profile2.json --layout wide --format tsv > genotypes.tsv`
+//!
+//! For downstream genomic tools, `mhrs vcf defn.csv profile1.json profile2.json > calls.vcf` writes
+//! the genotype calls in VCF format, with one record per marker and one sample column per profile.
+//! Each record is positioned at the marker's first ADS, with the positions of all ADSs listed in
+//! the `ADS` INFO field. Called haplotypes are reported as symbolic ALT alleles, e.g. `<CCACTGG>`,
+//! and each sample's `GT`, `AD` (read count of each allele), and `DP` (mean read depth) are given.
+//! If the profiles were typed with `--reference`, the reference haplotype is given by the `REFHAP`
+//! INFO field and serves as the REF allele (allele 0 in `GT`); otherwise REF is `N`.
+//!
+//! Genotype calls from two profiles, such as replicates of the same sample, can be compared marker
+//! by marker with `mhrs compare first.json second.json`. Like `mhrs mixture`, `mhrs rmp`, and `mhrs
+//! kinship`, this command also accepts a JSON array holding exactly one profile. Before typing with
+//! a new panel, `mhrs validate-panel defn.csv` checks the allele definitions for errors such as
+//! duplicate ADS coordinates and, given `--reference`, chromosome names or coordinates absent from
+//! the reference.
+//!
+//! Each typing result also reports a status: `ok` if reads were observed at the marker, `no_reads`
+//! if no reads overlap the marker, or `contig_not_found` if the marker's chromosome is absent from
+//! the alignment header—most often a naming mismatch such as `chr1` vs `1`. Markers that cannot be
+//! located are skipped with a warning, or cause mhrs to fail if `--strict-contigs` is specified.
+//! Chromosome names that differ between the allele definitions and the alignments can be mapped
+//! with `--contig-aliases`, a CSV file with a header row whose first column lists the names used in
+//! the allele definitions and whose second column lists the names used in the alignments.
+//!
+//! Typing results that warrant manual review are annotated with QC flags: `low_coverage` if fewer
+//! than `--min-coverage` reads span the marker, `no_alleles_passing` if no allele passes the
+//! thresholds, `possible_mixture` if more than `--max-alleles` alleles are called,
+//! `heterozygote_imbalance` if the minor allele of a heterozygote has less than `--min-het-balance`
+//! times the reads of the major allele, and `high_discard_rate` if more than `--max-discard-rate`
+//! of reads were discarded. Flags do not alter the genotype call.
+//!
+//! If mhrs cannot complete a command, it prints an error message identifying the file (and, for
+//! CSV input, the line) at fault and exits with a status indicating the kind of problem: 2 for
+//! invalid command-line arguments, 3 for files that could not be read or written, 4 for malformed
+//! CSV input, 5 for alignments that could not be read, 6 for malformed JSON, and 7 for inputs that
+//! are inconsistent with one another (such as a CRAM file without a matching reference).
+//!
+//! ### Appendix A: expected format for allele definitions
+//!
+//! Each line in the table corresponds to a single ADS. The coordinate is 0-based, that is, its
+//! distance from the chromosome's first nucleotide.
+//!
+//! ```csv
+//! Marker,Chrom,OffsetHg38
+//! mh01WL-006.v3,chr1,236518813
+//! mh01WL-006.v3,chr1,236518863
+//! mh01WL-006.v3,chr1,236518878
+//! mh01WL-006.v3,chr1,236518884
+//! mh01WL-006.v3,chr1,236518947
+//! mh01WL-006.v3,chr1,236519007
+//! mh01WL-006.v3,chr1,236519025
+//! mh02KK-134.v2,chr2,160222899
+//! mh02KK-134.v2,chr2,160222923
+//! mh02KK-134.v2,chr2,160222938
+//! mh02KK-134.v2,chr2,160222944
+//! mh02KK-134.v2,chr2,160222986
+//! mh02KK-134.v2,chr2,160223002
+//! mh04FHL-005.v6,chr4,81722743
+//! mh04FHL-005.v6,chr4,81722769
+//! mh04FHL-005.v6,chr4,81722790
+//! mh04FHL-005.v6,chr4,81722818
+//! mh04FHL-005.v6,chr4,81722856
+//! ```
+//!
+//! ### Appendix B: expected format for locus-specific typing thresholds
+//!
+//! Default thresholds are applied to any microhaps absent from this file by present in the allele
+//! definition file. An optional fourth column specifies a locus-specific minimum mapping quality;
+//! the `--min-mapq` default is applied where this column is absent or empty.
+//!
+//! ```csv
+//! Marker,Detection,Analytical
+//! mh03USC-3qC.v2,10,0.039
+//! mh04WL-052.v1,10,0.031
+//! mh06SCUZJ-0528857,20,0.041
+//! mh17FHL-005.v3,10,0.027
+//! ```
+//!
+//! ### Appendix C: expected format for population allele frequencies
+//!
+//! Each line gives the frequency of a single allele of a single microhap in a single population.
+//! Frequencies for any number of populations can be provided in a single file.
+//!
+//! ```csv
+//! Marker,Haplotype,Population,Frequency
+//! mh03USC-3qC.v2,CCACTGG,EUR,0.30
+//! mh03USC-3qC.v2,CTACTGG,EUR,0.20
+//! mh03USC-3qC.v2,CCACTGG,AFR,0.25
+//! mh03USC-3qC.v2,CTACTGG,AFR,0.15
+//! ```
+//!
+//! ### Appendix D: expected format for batch sample sheets
+//!
+//! Each line gives the name of a single sample and the path of its BAM or CRAM file, relative to
+//! the directory containing the sample sheet unless absolute. The optional third and fourth columns
+//! give the sample's default detection and analytical thresholds; if empty or absent, the values of
+//! `--static` and `--dynamic` are used. Locus-specific thresholds still take precedence. Sample
+//! names must be unique and are used to name output files, so they cannot contain `/` or `\` or be
+//! `.` or `..`.
+//!
+//! ```csv
+//! Sample,Alignments,Detection,Analytical
+//! Item1,run1/Item1.bam,,
+//! Item2,run1/Item2.bam,5,0.02
+//! Item3,run1/Item3.bam
+//! ```
+//!
+//! ### Library usage
+//!
+//! The typing workflow is also available as a library for embedding in other Rust programs. The
+//! [`MicrohapAnalyzer`] runs the complete workflow for a sample, while the building blocks it uses
+//! ([`MicrohapPanel`], [`HaplotypeObserver`], [`HaplotypeCaller`]) can be used directly for finer
+//! control. Typing results are collected in a [`MicrohapProfile`], which can be serialized to and
+//! from JSON.
+//!
+//! ```
+//! use mhrs::{MicrohapAnalyzer, MicrohapProfile, TypingParameters};
+//! use std::path::{Path, PathBuf};
+//!
+//! # fn main() -> mhrs::Result<()> {
+//! let mut analyzer = MicrohapAnalyzer::new("Item2", &PathBuf::from("testdata/mwgfour.csv"))?;
+//! analyzer.parameters = TypingParameters::defaults();
+//! analyzer.process(Path::new("testdata/mwgfour-p2.bam"))?;
+//! let json = analyzer.final_profile().to_json();
+//!
+//! let profile = MicrohapProfile::from_json(&json)?;
+//! let result = profile.get("mh03USC-3qC.v2").unwrap();
+//! assert_eq!(result.genotype_string(), "CCACTGG/CTACTGG");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Fallible functions return [`Result`], whose [`Error`] identifies the kind of failure (I/O, CSV,
+//! BAM, JSON, or validation) along with the file and, where applicable, the line at fault.
+
+/// Opening indexed BAM and CRAM files.
+pub mod alignments;
+/// End-to-end typing of a panel for a single sample.
+pub mod analyzer;
+/// Sample sheets and outcome summaries for typing many samples in a batch.
+pub mod batch;
+/// Genotype calling from observed read haplotypes.
+pub mod caller;
+/// Marker-by-marker comparison of two profiles.
+pub mod compare;
+/// Serializable read counts for observed haplotypes.
+pub mod counter;
+/// Allele definitions: the ADS coordinates of a single microhap.
+pub mod definition;
+/// Errors reported by mhrs.
+pub mod error;
+/// Export of profiles as flat CSV/TSV tables.
+pub mod export;
+/// Population allele frequency tables.
+pub mod frequencies;
+/// Likelihood ratios for relationship testing between two profiles.
+pub mod kinship;
+/// Mixture proportion estimation and deconvolution of two-person mixtures.
+pub mod mixture;
+/// Observation of read haplotypes from BAM pileups.
+pub mod observer;
+/// Panels of microhap allele definitions.
+pub mod panel;
+/// Parameters for configuring haplotype calling.
+pub mod parameters;
+/// Typing results for all microhaps in a panel for a single sample.
+pub mod profile;
+/// Haplotypes observed in individual reads.
+pub mod read;
+/// Indexed reference genome sequences.
+pub mod reference;
+/// Typing results for a single microhap.
+pub mod result;
+/// Random match probabilities of single-source profiles.
+pub mod rmp;
+/// Panel-wide and marker-specific typing thresholds.
+pub mod thresholds;
+/// Single-pass observation of every microhap in a panel.
+pub mod traversal;
+/// Genotype calls of one or more profiles in VCF format.
+pub mod vcf;
+
+pub use analyzer::MicrohapAnalyzer;
+pub use caller::HaplotypeCaller;
+pub use definition::AlleleDefinition;
+pub use error::{Error, Result};
+pub use observer::HaplotypeObserver;
+pub use panel::MicrohapPanel;
+pub use parameters::TypingParameters;
+pub use profile::MicrohapProfile;
+pub use read::ReadHaplotype;
+pub use result::TypingResult;
@@ -8,186 +8,318 @@
 // Development Center.
 // -------------------------------------------------------------------------------------------------
 
-//! ## mhrs: an empirical microhaplotype calling algorithm written in Rust
-//!
-//! ### Quick reference for the impatient
-//!
-//! Provided for convenience if this isn't your first rodeo:
-//!
-//! `mhrs defn.csv aligns.bam --static=12 --dynamic=0.025 > profile.json`
-//!
-//! Otherwise, read on for more details.
-//!
-//! ### User guide
-//!
-//! This program is designed to genotype specific microhaplotype (*microhap* or *MH*) markers using
-//! NGS (e.g. Illumina) reads. It's theoretically possible that the reads could come from a
-//! whole-genome shotgun sequencing strategy, but the coverage needed for confident genotype calls
-//! will typically require targeting the desired regions with something like multiplex PCR
-//! amplification or hybridization capture enrichment. This program does not *discover* new MH loci,
-//! it is designed only to *genotype* pre-determined user-specified markers.
-//!
-//! To demonstrate how mhrs works, consider the microhap `mh16WL-040.v1` shown below. It is
-//! comprised of four SNPs spanning 24 bp on chromosome 16.
-//!
-//! ```text
-//!           *    **                *
-//! AGGCTTCAGGCGGCTACCCGTGAAATCCCAGGTGCTTACCACAA
-//! ```
-//!
-//! Given those SNP locations and a set of reads aligned to that location, mhrs begins by
-//! determining the haplotype observed for each individual read—in other words, the combination of
-//! alleles observed at the allele-defining SNPs (ADSs). All other positions are ignored, leaving
-//! (in this case) a 4 bp sequence as the "allele".
-//!
-//! ```text
-//! AGGCTTCAGGCGGCTACCCGTGAAATCCCAGGTGCTTACCACAA
-//! ..........T....CT..............
-//! ....G.....C....CC................G...
-//! ..........C....CC................G.......
-//! ..........C....CC................G..........
-//! ..........T....CT................C..........
-//! ..........C....CC................G..........
-//! ..........T....CT................C..........
-//! ..........T....AT................C..........
-//! ..........T....CT.......C........C..........
-//! ..........C....CC................G..........
-//!    .......T....CT................C..........
-//!       ....C....CC................G..........
-//!             ...CT................C..........
-//! ```
-//!
-//! In the example above, we see six reads with the `CCCG` allele, four reads with the `TCTC`
-//! allele, and one read with the `TATC` allele. The first and last reads are discarded since they
-//! do not fully span all four ADSs and therefore are unsuitable for empirical haplotype calling.
-//! Two reads appear to have sequencing errors at non-target positions, but this does not impact
-//! haplotype calling.
-//!
-//! Next, mhrs applies thresholds to distinguish false alleles (due e.g. to sequencing error) from
-//! true alleles. In the example above, the `TATC` allele is likely a false allele. First, a fixed
-//! *detection threshold* is applied to eliminate obvious low-level noise. Next, a dynamic
-//! *analytical threshold* is computed as a percentage of the total read counts for alleles not
-//! eliminated by the detection treshold. The alleles that remain after filters are applied
-//! constitute the genotype call for that microhap.
-//!
-//! Running the mhrs program requires 1) allele definitions (in CSV format) listing ADS coordinates
-//! for a panel of microhap targets and 2) read alignments (in BAM format) against the human genome.
-//! It is expected that paired-end reads are merged prior to alignment. The expected format of the
-//! allele definition CSV is shown in Appendix A below.
-//!
-//! The user can configure the filtering behavior of the mhrs program with panel-wide default
-//! thresholds, locus-specific thresholds (if validation studies have been performed), or a
-//! combination of both. Default thresholds can be configured using command-line arguments (run
-//! `mhrs --help` for more information), while locus-specific thresholds can be provided in a CSV
-//! file, the expected format of which is shown in Appendix B below.
-//!
-//! The program generates a *typing result* for each microhap in the panel comprised of the genotype
-//! call, per-base read coverage statistics, and raw read counts (excluding partial observations for
-//! reads that don't span all ADSs). The typing results for all microhaps in the panel constitute a
-//! *profile*, which is reported in JSON format. A profile containing a single typing result, based
-//! on the example above, is shown here.
-//!
-//! ```json
-//! {
-//!   "sample_id": "Sample001",
-//!   "typing_results": {
-//!     "mh16WL-040.v1": {
-//!       "genotype": [
-//!         "CCCG",
-//!         "TCTC",
-//!       ],
-//!       "coverage": {
-//!         "max": 11,
-//!         "mean": 10.568181818181818,
-//!         "min": 9
-//!       },
-//!       "num_discarded": 1,
-//!       "thresholds": {
-//!         "dynamic": 0.02,
-//!         "analytical": 0.2,
-//!         "detection": 2
-//!       },
-//!       "counts": {
-//!         "CCCG": 6,
-//!         "TATC": 1,
-//!         "TCTC": 4
-//!       }
-//!     }
-//!   }
-//! }
-//! ```
-//!
-//! ### Appendix A: expected format for allele definitions
-//!
-//! Each line in the table corresponds to a single ADS. The coordinate is 0-based, that is, its
-//! distance from the chromosome's first nucleotide.
-//!
-//! ```csv
-//! Marker,Chrom,OffsetHg38
-//! mh01WL-006.v3,chr1,236518813
-//! mh01WL-006.v3,chr1,236518863
-//! mh01WL-006.v3,chr1,236518878
-//! mh01WL-006.v3,chr1,236518884
-//! mh01WL-006.v3,chr1,236518947
-//! mh01WL-006.v3,chr1,236519007
-//! mh01WL-006.v3,chr1,236519025
-//! mh02KK-134.v2,chr2,160222899
-//! mh02KK-134.v2,chr2,160222923
-//! mh02KK-134.v2,chr2,160222938
-//! mh02KK-134.v2,chr2,160222944
-//! mh02KK-134.v2,chr2,160222986
-//! mh02KK-134.v2,chr2,160223002
-//! mh04FHL-005.v6,chr4,81722743
-//! mh04FHL-005.v6,chr4,81722769
-//! mh04FHL-005.v6,chr4,81722790
-//! mh04FHL-005.v6,chr4,81722818
-//! mh04FHL-005.v6,chr4,81722856
-//! ```
-//!
-//! ### Appendix B: expected format for locus-specific typing thresholds
-//!
-//! Default thresholds are applied to any microhaps absent from this file by present in the allele
-//! definition file.
-//!
-//! ```csv
-//! Marker,Detection,Analytical
-//! mh03USC-3qC.v2,10,0.039
-//! mh04WL-052.v1,10,0.031
-//! mh06SCUZJ-0528857,20,0.041
-//! mh17FHL-005.v3,10,0.027
-//! ```
-
-mod analyzer;
-mod caller;
-mod counter;
-mod definition;
-mod observer;
-mod panel;
-mod parameters;
-mod profile;
-mod read;
-mod result;
-mod thresholds;
+//! Command-line interface for mhrs; see the library documentation for the user guide.
 
-#[doc(hidden)]
 mod cli;
 
-use analyzer::MicrohapAnalyzer;
-use clap::Parser;
-use cli::Cli;
-use parameters::TypingParameters;
+use cli::{
+    BatchArgs, Cli, Command, CompareArgs, ExportArgs, KinshipArgs, MixtureArgs, RmpArgs, TypeArgs,
+    TypingArgs, ValidatePanelArgs, VcfArgs,
+};
+use mhrs::alignments::{AlignmentFile, ContigAliases};
+use mhrs::batch::{BatchSummary, SampleEntry, SampleSheet};
+use mhrs::caller::NeighborRatio;
+use mhrs::compare::ProfileComparison;
+use mhrs::export::ProfileExporter;
+use mhrs::frequencies::FrequencyTable;
+use mhrs::kinship::{KinshipModel, KinshipTest};
+use mhrs::mixture::MixtureDeconvolution;
+use mhrs::panel::Severity;
+use mhrs::parameters::QcLimits;
+use mhrs::reference::ReferenceFasta;
+use mhrs::result::MarkerStatus;
+use mhrs::rmp::{MatchProbability, PopulationModel};
+use mhrs::vcf::VcfWriter;
+use mhrs::{Error, MicrohapAnalyzer, MicrohapPanel, MicrohapProfile, Result, TypingParameters};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
 
 #[doc(hidden)]
 fn main() {
-    let args = Cli::parse();
-    let mut analyzer = MicrohapAnalyzer::new(&args.sample, &args.csv);
+    let args = Cli::parse_with_legacy(std::env::args_os());
+    let outcome = match &args.command {
+        Command::Type(args) => type_sample(args),
+        Command::Batch(args) => type_batch(args),
+        Command::Export(args) => export_profiles(args),
+        Command::Vcf(args) => write_vcf(args),
+        Command::Compare(args) => compare_profiles(args),
+        Command::Mixture(args) => estimate_mixture(args),
+        Command::Rmp(args) => match_probability(args),
+        Command::Kinship(args) => test_kinship(args),
+        Command::ValidatePanel(args) => validate_panel(args),
+    };
+    if let Err(error) = outcome {
+        eprintln!("error: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+#[doc(hidden)]
+fn type_sample(args: &TypeArgs) -> Result<()> {
+    let sample = match (&args.sample, args.split_by) {
+        (Some(sample), _) => sample.clone(),
+        (None, Some(_)) => String::new(),
+        (None, None) => {
+            AlignmentFile::new(&args.bam, args.typing.reference.as_ref()).sample_name()?
+        }
+    };
+    let mut analyzer = MicrohapAnalyzer::new(&sample, &args.csv)?;
+    configure(&mut analyzer, &args.typing)?;
+    if let Some(split) = args.split_by {
+        let profiles = analyzer.process_split(&args.bam, split)?;
+        for profile in &profiles {
+            warn_missing_contigs(profile);
+        }
+        println!("{}", serde_json::to_string_pretty(&profiles)?);
+        return Ok(());
+    }
+    analyzer.process(&args.bam)?;
+    warn_missing_contigs(analyzer.final_profile());
+    println!("{}", analyzer.final_profile().to_json());
+    Ok(())
+}
+
+#[doc(hidden)]
+fn type_batch(args: &BatchArgs) -> Result<()> {
+    let mut analyzer = MicrohapAnalyzer::new("", &args.csv)?;
+    configure(&mut analyzer, &args.typing)?;
+    let sheet = SampleSheet::from_csv(&args.samplesheet)?;
+    if args.combined.is_none() {
+        fs::create_dir_all(&args.outdir).map_err(|e| Error::io(&args.outdir, e))?;
+    }
+    let mut summary = BatchSummary::new();
+    let mut profiles = Vec::new();
+    for entry in sheet.iter() {
+        eprintln!("typing sample {}", entry.sample);
+        let outcome = type_entry(&mut analyzer, args, entry).and_then(|profile| {
+            warn_missing_contigs(&profile);
+            if args.combined.is_some() {
+                profiles.push(profile);
+                return Ok(());
+            }
+            let path = args.outdir.join(format!("{}.json", entry.sample));
+            fs::write(&path, profile.to_json()).map_err(|e| Error::io(&path, e))
+        });
+        if let Err(error) = &outcome {
+            eprintln!("warning: sample {} failed: {}", entry.sample, error);
+        }
+        summary.record(&entry.sample, &outcome);
+    }
+    if let Some(path) = &args.combined {
+        let json = serde_json::to_string_pretty(&profiles)?;
+        fs::write(path, json).map_err(|e| Error::io(path, e))?;
+    }
+    print!("{}", summary.to_table());
+    eprintln!("{}", summary.summary());
+    if summary.failures() > 0 {
+        let message = format!("{} of {} samples failed", summary.failures(), sheet.len());
+        return Err(Error::Validation(message));
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+fn type_entry(
+    analyzer: &mut MicrohapAnalyzer,
+    args: &BatchArgs,
+    entry: &SampleEntry,
+) -> Result<MicrohapProfile> {
+    let detection = entry.detection.unwrap_or(args.typing.detection_threshold);
+    let analytical = entry.analytical.unwrap_or(args.typing.analytical_threshold);
+    analyzer
+        .parameters
+        .detection_threshold
+        .set_default(detection);
+    analyzer
+        .parameters
+        .analytical_threshold
+        .set_default(analytical);
+    analyzer.process_sample(&entry.sample, &entry.alignments)
+}
+
+#[doc(hidden)]
+fn configure(analyzer: &mut MicrohapAnalyzer, args: &TypingArgs) -> Result<()> {
     analyzer.parameters = TypingParameters::new(
         args.detection_threshold,
         args.analytical_threshold,
         args.min_base_quality,
+        args.min_mapq,
         args.max_depth,
         args.threshold_csv.as_ref(),
+    )?;
+    analyzer.parameters.caller = args.caller;
+    analyzer.parameters.rounding = args.rounding;
+    analyzer.parameters.neighbor_ratio = args.neighbor_ratio.map(NeighborRatio::new).transpose()?;
+    analyzer.parameters.paired = args.paired;
+    analyzer.parameters.strict_contigs = args.strict_contigs;
+    analyzer.parameters.qc = QcLimits {
+        min_coverage: args.min_coverage,
+        max_alleles: args.max_alleles,
+        min_het_balance: args.min_het_balance,
+        max_discard_rate: args.max_discard_rate,
+        min_mixture_markers: args.mixture_markers,
+    };
+    if let Some(path) = &args.contig_aliases {
+        analyzer.aliases = ContigAliases::from_csv(path)?;
+    }
+    analyzer.threads = args.threads as usize;
+    analyzer.reference = args.reference.clone();
+    for issue in analyzer.load_reference()? {
+        eprintln!("warning: marker {}: {}", issue.marker, issue.message);
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+fn warn_missing_contigs(profile: &MicrohapProfile) {
+    for (mhid, result) in profile.iter() {
+        if result.status == MarkerStatus::ContigNotFound {
+            eprintln!(
+                "warning: marker {} not typed; its chromosome is absent from the alignment header",
+                mhid
+            );
+        }
+    }
+}
+
+#[doc(hidden)]
+fn export_profiles(args: &ExportArgs) -> Result<()> {
+    let profiles = load_profiles(&args.profiles)?;
+    let exporter = ProfileExporter::new(profiles);
+    match &args.out {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| Error::io(path, e))?;
+            exporter.write(file, path, args.layout, args.format)
+        }
+        None => exporter.write(io::stdout(), Path::new("-"), args.layout, args.format),
+    }
+}
+
+#[doc(hidden)]
+fn write_vcf(args: &VcfArgs) -> Result<()> {
+    let panel = MicrohapPanel::from_csv(&args.csv)?;
+    let profiles = load_profiles(&args.profiles)?;
+    let writer = VcfWriter::new(panel, profiles)?;
+    match &args.out {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| Error::io(path, e))?;
+            writer.write(io::BufWriter::new(file))
+        }
+        None => writer.write(io::stdout().lock()),
+    }
+}
+
+#[doc(hidden)]
+fn load_profiles(paths: &[PathBuf]) -> Result<Vec<MicrohapProfile>> {
+    let mut profiles = Vec::new();
+    for path in paths {
+        profiles.extend(MicrohapProfile::all_from_path(path)?);
+    }
+    Ok(profiles)
+}
+
+#[doc(hidden)]
+fn load_single_profile(path: &Path) -> Result<MicrohapProfile> {
+    let mut profiles = MicrohapProfile::all_from_path(path)?;
+    if profiles.len() != 1 {
+        return Err(Error::Validation(format!(
+            "{} holds {} profiles; expected exactly one",
+            path.display(),
+            profiles.len()
+        )));
+    }
+    Ok(profiles.remove(0))
+}
+
+#[doc(hidden)]
+fn compare_profiles(args: &CompareArgs) -> Result<()> {
+    let first = load_single_profile(&args.first)?;
+    let second = load_single_profile(&args.second)?;
+    let comparison = ProfileComparison::new(&first, &second);
+    print!("{}", comparison.to_table());
+    eprintln!("{}", comparison.summary());
+    Ok(())
+}
+
+#[doc(hidden)]
+fn estimate_mixture(args: &MixtureArgs) -> Result<()> {
+    let profile = load_single_profile(&args.profile)?;
+    let known = match &args.known {
+        Some(path) => Some(load_single_profile(path)?),
+        None => None,
+    };
+    let mixture = MixtureDeconvolution::new(&profile, known.as_ref())?;
+    print!("{}", mixture.to_table());
+    for mhid in mixture.skipped() {
+        eprintln!(
+            "warning: marker {} skipped; its alleles cannot be explained by two contributors",
+            mhid
+        );
+    }
+    eprintln!("{}", mixture.summary());
+    Ok(())
+}
+
+#[doc(hidden)]
+fn match_probability(args: &RmpArgs) -> Result<()> {
+    let profile = load_single_profile(&args.profile)?;
+    let table = FrequencyTable::from_csv(&args.frequencies)?;
+    let model = PopulationModel::new(args.theta, args.min_frequency)?;
+    let rmp = MatchProbability::new(&profile, &table, &args.populations, &model)?;
+    print!("{}", rmp.to_table());
+    for mhid in rmp.skipped() {
+        eprintln!(
+            "warning: marker {} excluded; RMP requires one or two alleles called",
+            mhid
+        );
+    }
+    eprintln!("{}", rmp.summary());
+    Ok(())
+}
+
+#[doc(hidden)]
+fn test_kinship(args: &KinshipArgs) -> Result<()> {
+    let first = load_single_profile(&args.first)?;
+    let second = load_single_profile(&args.second)?;
+    let table = FrequencyTable::from_csv(&args.frequencies)?;
+    let model = KinshipModel::new(args.allowance, args.min_frequency)?;
+    let kinship = KinshipTest::new(&first, &second, &table, &args.population, &model)?;
+    print!("{}", kinship.to_table());
+    for mhid in kinship.skipped() {
+        eprintln!(
+            "warning: marker {} skipped; it must be typed with one or two alleles in both profiles",
+            mhid
+        );
+    }
+    eprintln!("{}", kinship.summary());
+    Ok(())
+}
+
+#[doc(hidden)]
+fn validate_panel(args: &ValidatePanelArgs) -> Result<()> {
+    let panel = MicrohapPanel::from_csv(&args.csv)?;
+    let reference = match &args.reference {
+        Some(path) => Some(ReferenceFasta::from_path(path)?),
+        None => None,
+    };
+    let issues = panel.validate(reference.as_ref());
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    eprintln!(
+        "{} markers checked: {} errors, {} warnings",
+        panel.len(),
+        errors,
+        issues.len() - errors
     );
-    analyzer.process(&args.bam);
-    println!("{}", analyzer.final_profile().to_json());
+    if errors > 0 {
+        let message = format!("panel {} failed validation", args.csv.display());
+        return Err(Error::Validation(message));
+    }
+    Ok(())
 }
@@ -0,0 +1,351 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+use crate::error::{Error, Result};
+use crate::profile::MicrohapProfile;
+use crate::read::ReadHaplotype;
+use crate::result::TypingResult;
+
+/// Step between candidate mixture proportions.
+const GRID_STEP: f64 = 0.001;
+
+/// Genotypes of the two contributors to a mixture at a single microhap, with the proportion
+/// estimated from that microhap alone.
+pub struct MarkerDeconvolution {
+    pub marker: String,
+    pub first: Vec<ReadHaplotype>,
+    pub second: Vec<ReadHaplotype>,
+    pub proportion: f64,
+}
+
+/// Estimate of the mixture proportion of a two-person mixture from the read counts of the alleles
+/// called at each microhap, along with the most likely genotypes of each contributor.
+///
+/// Each contributor is assumed to contribute reads in proportion to its share of the DNA, split
+/// evenly between its two alleles, so that the expected read fraction of each called allele is
+/// determined by the mixture proportion and the contributors' genotypes. For a candidate
+/// proportion, each microhap contributes the multinomial log likelihood of its allele read counts
+/// under the most likely pair of genotypes that together explain all of the called alleles, and
+/// the proportion maximizing the total is reported. If the profile of a known contributor is
+/// given, the first contributor's genotypes are fixed to the known profile and the proportion is
+/// that of the known contributor; otherwise the first contributor is the major contributor.
+///
+/// Read counts are overdispersed relative to the multinomial model, so the confidence interval is
+/// derived from the spread of the proportions estimated from each informative microhap alone.
+/// Microhaps with a single allele called carry no information about the proportion and are
+/// skipped, as are microhaps with more than four alleles called or with called alleles absent from
+/// the known contributor's genotype.
+pub struct MixtureDeconvolution {
+    known: bool,
+    proportion: f64,
+    interval: Option<(f64, f64)>,
+    markers: Vec<MarkerDeconvolution>,
+    skipped: Vec<String>,
+}
+
+/// Alleles called at a single microhap with their read counts, and the candidate genotype pairs.
+struct MarkerModel<'a> {
+    mhid: &'a str,
+    alleles: Vec<(ReadHaplotype, usize)>,
+    pairs: Vec<(Vec<usize>, Vec<usize>)>,
+}
+
+impl MarkerModel<'_> {
+    /// Log likelihood of the read counts at the given proportion under the most likely genotype
+    /// pair, and the index of that pair.
+    fn log_likelihood(&self, proportion: f64) -> (f64, usize) {
+        let mut best = (f64::NEG_INFINITY, 0);
+        for (index, (first, second)) in self.pairs.iter().enumerate() {
+            let ll: f64 = self
+                .alleles
+                .iter()
+                .enumerate()
+                .map(|(allele, (_, count))| {
+                    let dose1 = first.iter().filter(|&&a| a == allele).count() as f64 / 2.0;
+                    let dose2 = second.iter().filter(|&&a| a == allele).count() as f64 / 2.0;
+                    let fraction = proportion * dose1 + (1.0 - proportion) * dose2;
+                    *count as f64 * fraction.ln()
+                })
+                .sum();
+            if ll > best.0 {
+                best = (ll, index);
+            }
+        }
+        best
+    }
+
+    /// Alleles of the given genotype, as indices into `alleles`.
+    fn genotype(&self, indices: &[usize]) -> Vec<ReadHaplotype> {
+        let mut genotype: Vec<ReadHaplotype> =
+            indices.iter().map(|&i| self.alleles[i].0.clone()).collect();
+        genotype.dedup();
+        genotype
+    }
+}
+
+impl MixtureDeconvolution {
+    /// Estimate the mixture proportion of the given profile, optionally with the profile of a known
+    /// contributor. An error is returned if no microhap is informative.
+    pub fn new(
+        profile: &MicrohapProfile,
+        known: Option<&MicrohapProfile>,
+    ) -> Result<MixtureDeconvolution> {
+        let mut models = Vec::new();
+        let mut skipped = Vec::new();
+        for (mhid, result) in profile.iter() {
+            if result.genotype.len() < 2 {
+                continue;
+            }
+            let known_genotype = known.map(|known| known.get(mhid).map(|r| &r.genotype));
+            match Self::marker_model(mhid, result, known_genotype) {
+                Some(model) => models.push(model),
+                None => skipped.push(mhid.clone()),
+            }
+        }
+        if models.is_empty() {
+            return Err(Error::Validation(format!(
+                "no microhaps in profile {} are informative about the mixture proportion",
+                profile.sample_id()
+            )));
+        }
+        let grid: Vec<f64> = match known {
+            Some(_) => Self::grid(GRID_STEP, 1.0 - GRID_STEP),
+            None => Self::grid(0.5, 1.0 - GRID_STEP),
+        };
+        let argmax = |objective: &dyn Fn(f64) -> f64| {
+            grid.iter()
+                .copied()
+                .max_by(|p1, p2| objective(*p1).total_cmp(&objective(*p2)))
+                .unwrap()
+        };
+        let proportion = argmax(&|p| models.iter().map(|m| m.log_likelihood(p).0).sum());
+        let markers: Vec<MarkerDeconvolution> = models
+            .iter()
+            .map(|model| {
+                let (_, index) = model.log_likelihood(proportion);
+                let (first, second) = &model.pairs[index];
+                MarkerDeconvolution {
+                    marker: model.mhid.to_string(),
+                    first: model.genotype(first),
+                    second: model.genotype(second),
+                    proportion: argmax(&|p| model.log_likelihood(p).0),
+                }
+            })
+            .collect();
+        let interval = match markers.len() {
+            0 | 1 => None,
+            n => {
+                let estimates: Vec<f64> = markers.iter().map(|m| m.proportion).collect();
+                let mean = estimates.iter().sum::<f64>() / n as f64;
+                let variance =
+                    estimates.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+                let margin = 1.96 * (variance / n as f64).sqrt();
+                Some((
+                    (proportion - margin).max(0.0),
+                    (proportion + margin).min(1.0),
+                ))
+            }
+        };
+        Ok(MixtureDeconvolution {
+            known: known.is_some(),
+            proportion,
+            interval,
+            markers,
+            skipped,
+        })
+    }
+
+    /// Candidate proportions from `start` to `end`, inclusive.
+    fn grid(start: f64, end: f64) -> Vec<f64> {
+        let steps = ((end - start) / GRID_STEP).round() as usize;
+        (0..=steps).map(|i| start + i as f64 * GRID_STEP).collect()
+    }
+
+    /// Enumerate the genotype pairs that explain the alleles called at a microhap. Returns `None`
+    /// if the microhap cannot be explained by two contributors.
+    fn marker_model<'a>(
+        mhid: &'a str,
+        result: &TypingResult,
+        known: Option<Option<&Vec<ReadHaplotype>>>,
+    ) -> Option<MarkerModel<'a>> {
+        if result.genotype.len() > 4 {
+            return None;
+        }
+        let alleles: Vec<(ReadHaplotype, usize)> = result
+            .genotype
+            .iter()
+            .map(|allele| (allele.clone(), result.counts.tally[allele]))
+            .collect();
+        let n = alleles.len();
+        let genotypes: Vec<Vec<usize>> = (0..n)
+            .flat_map(|i| (i..n).map(move |j| vec![i, j]))
+            .collect();
+        let firsts: Vec<Vec<usize>> = match known {
+            None => genotypes.clone(),
+            Some(None) => return None,
+            Some(Some(known)) => {
+                let indices: Option<Vec<usize>> = known
+                    .iter()
+                    .map(|allele| alleles.iter().position(|(a, _)| a == allele))
+                    .collect();
+                let mut indices = indices?;
+                if indices.is_empty() || indices.len() > 2 {
+                    return None;
+                }
+                if indices.len() == 1 {
+                    indices.push(indices[0]);
+                }
+                vec![indices]
+            }
+        };
+        let mut pairs = Vec::new();
+        for first in &firsts {
+            for second in &genotypes {
+                let covered = (0..n).all(|a| first.contains(&a) || second.contains(&a));
+                if covered {
+                    pairs.push((first.clone(), second.clone()));
+                }
+            }
+        }
+        match pairs.is_empty() {
+            true => None,
+            false => Some(MarkerModel {
+                mhid,
+                alleles,
+                pairs,
+            }),
+        }
+    }
+
+    /// Estimated proportion of the known contributor, or of the major contributor if no known
+    /// contributor was given.
+    pub fn proportion(&self) -> f64 {
+        self.proportion
+    }
+
+    /// Approximate 95% confidence interval of the proportion, if at least two microhaps are
+    /// informative.
+    pub fn interval(&self) -> Option<(f64, f64)> {
+        self.interval
+    }
+
+    /// Deconvolved genotypes at each informative microhap, sorted by name.
+    pub fn markers(&self) -> &[MarkerDeconvolution] {
+        &self.markers
+    }
+
+    /// Microhaps whose called alleles cannot be explained by two contributors.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Tab-separated table with one row per informative microhap: the genotype of each contributor
+    /// and the proportion estimated from that microhap alone.
+    pub fn to_table(&self) -> String {
+        let (first, second) = match self.known {
+            true => ("Known", "Unknown"),
+            false => ("Major", "Minor"),
+        };
+        let mut table = format!("Marker\t{}\t{}\tProportion\n", first, second);
+        for marker in &self.markers {
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{:.3}\n",
+                marker.marker,
+                join(&marker.first),
+                join(&marker.second),
+                marker.proportion
+            ));
+        }
+        table
+    }
+
+    /// One-line summary of the estimated proportion and its confidence interval.
+    pub fn summary(&self) -> String {
+        let contributor = match self.known {
+            true => "known",
+            false => "major",
+        };
+        let interval = match self.interval {
+            Some((low, high)) => format!(" (95% CI {:.3}-{:.3})", low, high),
+            None => String::new(),
+        };
+        format!(
+            "{} contributor proportion {:.3}{} from {} informative markers; {} markers skipped",
+            contributor,
+            self.proportion,
+            interval,
+            self.markers.len(),
+            self.skipped.len()
+        )
+    }
+}
+
+/// Genotype as a slash-separated list of alleles.
+fn join(genotype: &[ReadHaplotype]) -> String {
+    genotype
+        .iter()
+        .map(|allele| allele.to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_mixture_unknown() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1p3-profile.json")).unwrap();
+        let mixture = MixtureDeconvolution::new(&profile, None).unwrap();
+        assert!((mixture.proportion() - 0.855).abs() < 0.001);
+        let (low, high) = mixture.interval().unwrap();
+        assert!(low <= mixture.proportion() && mixture.proportion() <= high);
+        assert_eq!(mixture.markers().len(), 4);
+        assert!(mixture.skipped().is_empty());
+        let table = mixture.to_table();
+        assert!(table.starts_with("Marker\tMajor\tMinor\tProportion\n"));
+        assert!(mixture
+            .summary()
+            .starts_with("major contributor proportion"));
+    }
+
+    #[test]
+    fn test_mixture_known() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1p3-profile.json")).unwrap();
+        let known =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1-profile.json")).unwrap();
+        let mixture = MixtureDeconvolution::new(&profile, Some(&known)).unwrap();
+        assert!((mixture.proportion() - 0.855).abs() < 0.001);
+        for marker in mixture.markers() {
+            let expected = &known.get(&marker.marker).unwrap().genotype;
+            assert_eq!(&marker.first, expected);
+        }
+        assert!(mixture
+            .to_table()
+            .starts_with("Marker\tKnown\tUnknown\tProportion\n"));
+    }
+
+    #[test]
+    fn test_mixture_single_source() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1-profile.json")).unwrap();
+        let known =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let mixture = MixtureDeconvolution::new(&profile, Some(&known));
+        let message = mixture.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "no microhaps in profile Item1 are informative about the mixture proportion"
+        );
+    }
+}
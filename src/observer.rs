@@ -13,61 +13,192 @@ extern crate rust_htslib;
 
 use crate::definition::AlleleDefinition;
 use crate::read::ReadHaplotype;
+use crate::result::ReadRejections;
 use counter::Counter;
-use rust_htslib::{bam, bam::Read};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use rust_htslib::bam;
+use rust_htslib::bam::pileup::Pileup;
+use rust_htslib::bam::record::Aux;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::Utf8Error;
 
 /// Data structure that tracks observed alleles for individual reads. This is done by iterating over
 /// a BAM read pileup position-by-position. Thus, the `HaplotypeObserver` maintains an index of
-/// `ReadHaplotype` objects that are each populated SNP by SNP.
+/// `ReadHaplotype` objects that are each populated SNP by SNP. Reads that cannot contribute a
+/// complete haplotype are tracked so that the reason for their rejection can be reported.
+///
+/// By default, reads are assumed to have been merged prior to alignment. When `paired` is set, the
+/// two mates of a read pair are instead combined into a single fragment haplotype: each mate
+/// contributes the ADSs it covers, and where both mates cover the same ADS but disagree, the base
+/// with the higher quality is retained (or the ADS is marked N if the qualities are equal).
+///
+/// When `read_groups` is set, only reads whose `RG` tag names one of the given read groups are
+/// observed, and read depth counts only those reads; all other reads are ignored entirely.
 pub struct HaplotypeObserver {
+    pub paired: bool,
+    pub read_groups: Option<HashSet<String>>,
     definition: AlleleDefinition,
-    index: HashMap<String, ReadHaplotype>,
+    index: HashMap<String, ReadObservation>,
     depths: Vec<u32>,
+    flagged: HashSet<String>,
+    low_mapq: HashSet<String>,
+    capped: HashSet<String>,
+}
+
+/// Haplotype observed for a single read (or read pair), along with the base qualities supporting
+/// each allele and the information needed to explain why the haplotype is partial, if it is.
+struct ReadObservation {
+    haplotype: ReadHaplotype,
+    qualities: Vec<u8>,
+    covered: Vec<bool>,
+    mates: u8,
+    gap: bool,
+    low_quality: bool,
+    conflict: bool,
+}
+
+impl ReadObservation {
+    fn new(num_snps: usize) -> ReadObservation {
+        ReadObservation {
+            haplotype: ReadHaplotype::new(num_snps),
+            qualities: vec![0; num_snps],
+            covered: vec![false; num_snps],
+            mates: 0,
+            gap: false,
+            low_quality: false,
+            conflict: false,
+        }
+    }
+
+    /// Indicate whether the aligned extents of the observed reads cover every ADS.
+    fn spans(&self) -> bool {
+        self.covered.iter().all(|&c| c)
+    }
+
+    /// Store the allele observed at the given ADS. In paired mode, an allele already observed in
+    /// the other mate is only replaced by a conflicting allele of higher quality, and conflicting
+    /// alleles of equal quality leave the ADS unresolved.
+    fn observe(&mut self, index: usize, allele: char, quality: u8, paired: bool) {
+        let current = self.haplotype.get(index).unwrap();
+        if !paired || current == 'N' || quality > self.qualities[index] {
+            self.haplotype.set(index, allele);
+            self.qualities[index] = quality;
+        } else if current == allele {
+            self.qualities[index] = self.qualities[index].max(quality);
+        } else if quality == self.qualities[index] {
+            self.haplotype.set(index, 'N');
+            self.qualities[index] = 0;
+            self.conflict = true;
+        }
+    }
 }
 
 impl HaplotypeObserver {
+    /// Initialize an empty observer for the given microhap.
     pub fn new(definition: &AlleleDefinition) -> HaplotypeObserver {
         HaplotypeObserver {
+            paired: false,
+            read_groups: None,
             definition: definition.clone(),
             index: HashMap::new(),
             depths: Vec::new(),
+            flagged: HashSet::new(),
+            low_mapq: HashSet::new(),
+            capped: HashSet::new(),
         }
     }
 
-    /// Store the allele for the given SNP in the given read.
-    pub fn set(&mut self, read_name: &str, offset: u32, base: char) {
+    /// Store the allele for the given SNP in the given read, supported by a base of the given
+    /// quality.
+    pub fn set(&mut self, read_name: &str, offset: u32, base: char, quality: u8) {
         let num_snps = self.definition.num_snps();
-        let readhap = self
-            .index
-            .entry(read_name.to_string())
-            .or_insert(ReadHaplotype::new(num_snps));
+        let paired = self.paired;
         let index = match self.definition.get_index(offset) {
-            Some(i) => i,
+            Some(i) => *i,
             None => panic!("invalid offset: {}", offset),
         };
-        readhap.set(*index, base);
+        let observation = self
+            .index
+            .entry(read_name.to_string())
+            .or_insert_with(|| ReadObservation::new(num_snps));
+        observation.observe(index, base, quality, paired);
     }
 
     /// Tally and return observed haplotypes, ignoring partial haplotypes.
     pub fn count(&self) -> Counter<ReadHaplotype> {
-        let mut counts: Counter<ReadHaplotype> = self.index.values().cloned().collect();
+        let mut counts: Counter<ReadHaplotype> = self
+            .index
+            .values()
+            .map(|observation| observation.haplotype.clone())
+            .collect();
         counts.retain(|readhap, _| !readhap.is_partial());
         counts
     }
 
+    /// Complete haplotypes observed across reads, along with the quality of the base supporting
+    /// each allele. Reads with identical haplotypes and base qualities are reported once, with the
+    /// number of such reads.
+    pub fn complete_reads(&self) -> Vec<(ReadHaplotype, Vec<u8>, usize)> {
+        let mut groups: BTreeMap<(&ReadHaplotype, &[u8]), usize> = BTreeMap::new();
+        for observation in self.index.values() {
+            if !observation.haplotype.is_partial() {
+                let key = (&observation.haplotype, observation.qualities.as_slice());
+                *groups.entry(key).or_default() += 1;
+            }
+        }
+        groups
+            .into_iter()
+            .map(|((haplotype, qualities), count)| (haplotype.clone(), qualities.to_vec(), count))
+            .collect()
+    }
+
     /// Calculate the number of observed haplotypes that are discarded due to being partial.
     pub fn discarded(&self) -> usize {
-        let counts: Counter<ReadHaplotype> = self.index.values().cloned().collect();
-        counts
+        self.index
+            .values()
+            .filter(|observation| {
+                observation.haplotype.is_partial() && !observation.haplotype.is_empty()
+            })
+            .count()
+    }
+
+    /// Tally the reads overlapping this microhap that do not contribute a complete haplotype,
+    /// attributing each read to a single reason. Reads rejected outright are attributed to their
+    /// alignment flags, their mapping quality, or the depth cap. Partial haplotypes are attributed,
+    /// in order of precedence, to conflicting mates (paired mode only), a gap at an ADS, low base
+    /// quality at an ADS, or an alignment that does not span all ADSs; any remaining partial
+    /// haplotypes are attributed to the depth cap.
+    pub fn rejections(&self) -> ReadRejections {
+        let mut rejections = ReadRejections::default();
+        for observation in self.index.values() {
+            if !observation.haplotype.is_partial() {
+                continue;
+            }
+            if observation.conflict {
+                rejections.mate_conflict += 1;
+            } else if observation.gap {
+                rejections.gap += 1;
+            } else if observation.low_quality {
+                rejections.base_quality += 1;
+            } else if !observation.spans() {
+                rejections.partial_span += 1;
+            } else {
+                rejections.depth_capped += 1;
+            }
+        }
+        let unobserved = |read_name: &&String| !self.index.contains_key(*read_name);
+        rejections.flags = self.flagged.iter().filter(unobserved).count();
+        rejections.mapping_quality = self
+            .low_mapq
             .iter()
-            .filter(|(readhap, _)| readhap.is_partial())
-            .map(|(_, count)| count)
-            .sum()
+            .filter(unobserved)
+            .filter(|read_name| !self.flagged.contains(*read_name))
+            .count();
+        rejections.depth_capped += self.capped.iter().filter(unobserved).count();
+        rejections
     }
 
-    /// Calculate and return max, mean, and min per-base read coverage.
+    /// Calculate and return max, mean, and min per-base read coverage, all zero if no position of
+    /// the microhap was covered.
     pub fn coverage(&self) -> (u32, f64, u32) {
         let max = match self.depths.iter().max() {
             Some(d) => *d,
@@ -78,7 +209,10 @@ impl HaplotypeObserver {
             None => 0,
         };
         let total: u32 = Iterator::sum(self.depths.iter());
-        let mean: f64 = (total as f64) / (self.depths.len() as f64);
+        let mean: f64 = match self.depths.len() {
+            0 => 0.0,
+            n => (total as f64) / (n as f64),
+        };
 
         (max, mean, min)
     }
@@ -88,56 +222,122 @@ impl HaplotypeObserver {
         self.definition.is_ads(offset)
     }
 
-    /// Count observed haplotypes given the specified BAM file, ignoring positions with a quality
-    /// below `min_base_qual`.
-    pub fn call_from_bam(&mut self, bam_path: &PathBuf, min_base_qual: u8, max_depth: u32) {
-        let mut bam = bam::IndexedReader::from_path(bam_path).unwrap();
-        let _ = bam.fetch(self.definition.region());
-        let mut pileups = bam.pileup();
-        pileups.set_max_depth(max_depth);
-        for pileup in pileups {
-            let pileup = pileup.expect("error reading pileup");
-            let refr_pos = pileup.pos();
-            if refr_pos >= self.definition.start() && refr_pos <= self.definition.end() {
-                self.depths.push(pileup.depth());
+    /// Record read depth and, if the pileup is positioned at an ADS, the allele observed in each
+    /// read, ignoring reads with a mapping quality below `min_mapq` and positions with a quality
+    /// below `min_base_qual`. The pileup itself is expected to be capped at `max_depth` reads by
+    /// htslib, so reads it drops are never seen here. Once `max_depth` reads have been observed at
+    /// an ADS, any remaining reads in the pileup not already under observation are counted as depth
+    /// capped and ignored at this and all subsequent ADSs. Fails if a read name is not valid UTF-8.
+    pub fn observe_pileup(
+        &mut self,
+        pileup: &Pileup,
+        min_base_qual: u8,
+        min_mapq: u8,
+        max_depth: u32,
+    ) -> Result<(), Utf8Error> {
+        let refr_pos = pileup.pos();
+        if refr_pos >= self.definition.start() && refr_pos <= self.definition.end() {
+            let depth = match self.read_groups {
+                None => pileup.depth(),
+                Some(_) => pileup
+                    .alignments()
+                    .filter(|alignment| self.in_read_groups(&alignment.record()))
+                    .count() as u32,
+            };
+            self.depths.push(depth.min(max_depth));
+        }
+        if !self.is_ads(refr_pos) {
+            return Ok(());
+        }
+        let mut depth = 0;
+        for alignment in pileup.alignments() {
+            let record = alignment.record();
+            if !self.in_read_groups(&record) {
+                continue;
             }
-            if !self.is_ads(refr_pos) {
+            let read_name = std::str::from_utf8(record.qname())?;
+            if self.skip_record(&record, read_name, min_mapq) {
                 continue;
             }
-            for alignment in pileup.alignments() {
-                if let Some(qpos) = alignment.qpos() {
-                    let record = alignment.record();
-                    if Self::skip_record(&record, qpos, min_base_qual) {
-                        continue;
-                    }
-                    let is_gap = alignment.is_del() || alignment.is_refskip();
-                    let allele = match is_gap {
-                        true => '-',
-                        false => record.seq()[qpos] as char,
-                    };
-                    let read_name = std::str::from_utf8(record.qname()).unwrap();
-                    self.set(read_name, refr_pos, allele);
+            if !self.index.contains_key(read_name) && depth >= max_depth {
+                self.capped.insert(read_name.to_string());
+                continue;
+            }
+            depth += 1;
+            let observation = self.observation(read_name, &record);
+            match alignment.qpos() {
+                None => observation.gap = true,
+                Some(qpos) if record.qual()[qpos] < min_base_qual => observation.low_quality = true,
+                Some(qpos) => {
+                    let allele = record.seq()[qpos] as char;
+                    self.set(read_name, refr_pos, allele, record.qual()[qpos]);
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Reference to the allele definition for the microhap being observed.
+    pub fn definition(&self) -> &AlleleDefinition {
+        &self.definition
+    }
+
+    /// Retrieve the observation for the given read, creating it if this is the first time the read
+    /// has been observed. The first time each mate is seen, the ADSs covered by its alignment are
+    /// noted.
+    fn observation(&mut self, read_name: &str, record: &bam::Record) -> &mut ReadObservation {
+        let num_snps = self.definition.num_snps();
+        let offsets = self.definition.get_offsets();
+        let observation = self
+            .index
+            .entry(read_name.to_string())
+            .or_insert_with(|| ReadObservation::new(num_snps));
+        let mate = if record.is_last_in_template() { 2 } else { 1 };
+        if observation.mates & mate == 0 {
+            observation.mates |= mate;
+            let (start, end) = (record.pos(), record.cigar().end_pos());
+            for (covered, &offset) in observation.covered.iter_mut().zip(offsets) {
+                *covered |= start <= offset as i64 && (offset as i64) < end;
+            }
+        }
+        observation
+    }
+
+    /// Indicate whether the read belongs to one of the read groups under observation, if any.
+    fn in_read_groups(&self, record: &bam::Record) -> bool {
+        match &self.read_groups {
+            None => true,
+            Some(groups) => match record.aux(b"RG") {
+                Ok(Aux::String(id)) => groups.contains(id),
+                _ => false,
+            },
+        }
     }
 
-    fn skip_record(record: &bam::record::Record, qpos: usize, min_base_qual: u8) -> bool {
+    /// Indicate whether the read should be ignored entirely due to its alignment flags, mapping
+    /// quality, or the depth cap, noting the reason.
+    fn skip_record(&mut self, record: &bam::Record, read_name: &str, min_mapq: u8) -> bool {
         let ignore = record.is_secondary()
             || record.is_supplementary()
             || record.is_duplicate()
             || record.is_quality_check_failed();
         if ignore {
+            self.flagged.insert(read_name.to_string());
+            return true;
+        }
+        if record.mapq() < min_mapq {
+            self.low_mapq.insert(read_name.to_string());
             return true;
         }
-        let base_quality = record.qual()[qpos];
-        base_quality < min_base_qual
+        self.capped.contains(read_name)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rust_htslib::bam::Read;
+    use std::path::PathBuf;
 
     impl HaplotypeObserver {
         pub fn set_all(&mut self, read_name: &str, alleles: &str) {
@@ -145,12 +345,32 @@ mod tests {
             if alleles.len() != num_snps {
                 panic!("length mismatch: {} vs {}", alleles.len(), num_snps);
             }
-            let readhap = self
+            let observation = self
                 .index
                 .entry(read_name.to_string())
-                .or_insert(ReadHaplotype::new(num_snps));
+                .or_insert_with(|| ReadObservation::new(num_snps));
+            observation.covered.fill(true);
+            observation.qualities.fill(30);
             for (index, base) in alleles.chars().enumerate() {
-                readhap.set(index, base);
+                observation.haplotype.set(index, base);
+            }
+        }
+
+        pub fn call_from_bam(
+            &mut self,
+            bam_path: &PathBuf,
+            min_base_qual: u8,
+            min_mapq: u8,
+            max_depth: u32,
+        ) {
+            let mut bam = bam::IndexedReader::from_path(bam_path).unwrap();
+            bam.fetch(self.definition.region()).unwrap();
+            let mut pileups = bam.pileup();
+            pileups.set_max_depth(max_depth);
+            for pileup in pileups {
+                let pileup = pileup.expect("error reading pileup");
+                self.observe_pileup(&pileup, min_base_qual, min_mapq, max_depth)
+                    .unwrap();
             }
         }
     }
@@ -182,7 +402,7 @@ mod tests {
             vec![48665164, 48665175, 48665182, 48665204, 48665216],
         );
         let mut observer = HaplotypeObserver::new(&def);
-        observer.set("read42", 12345, 'C');
+        observer.set("read42", 12345, 'C', 30);
     }
 
     #[test]
@@ -195,4 +415,56 @@ mod tests {
         let mut observer = HaplotypeObserver::new(&def);
         observer.set_all("read42", "CAT");
     }
+
+    #[test]
+    fn test_observer_read_groups() {
+        let def = AlleleDefinition::from_vector(
+            "chr3",
+            vec![
+                196652865, 196652971, 196653025, 196653034, 196653044, 196653084, 196653121,
+            ],
+        );
+        let bam_path = PathBuf::from("testdata/mwgfour-multiplex.bam");
+        let mut pooled = HaplotypeObserver::new(&def);
+        pooled.call_from_bam(&bam_path, 10, 0, 1e6 as u32);
+        let mut total = 0;
+        let mut depth = 0.0;
+        for group in ["lib1", "lib2a", "lib2b"] {
+            let mut observer = HaplotypeObserver::new(&def);
+            observer.read_groups = Some(HashSet::from([group.to_string()]));
+            observer.call_from_bam(&bam_path, 10, 0, 1e6 as u32);
+            let count: usize = observer.count().values().sum();
+            assert!(count > 0);
+            total += count;
+            depth += observer.coverage().1;
+        }
+        assert_eq!(total, pooled.count().values().sum::<usize>());
+        assert!((depth - pooled.coverage().1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_observer_paired_mates() {
+        let def = AlleleDefinition::from_vector("chr22", vec![48665164, 48665175, 48665182]);
+        let mut observer = HaplotypeObserver::new(&def);
+        observer.paired = true;
+        observer.set("pair1", 48665164, 'A', 30);
+        observer.set("pair1", 48665175, 'C', 30);
+        observer.set("pair1", 48665175, 'C', 20);
+        observer.set("pair1", 48665182, 'G', 20);
+        observer.set("pair1", 48665182, 'T', 35);
+        observer.set("pair2", 48665164, 'A', 30);
+        observer.set("pair2", 48665175, 'C', 30);
+        observer.set("pair2", 48665182, 'G', 25);
+        observer.set("pair2", 48665182, 'T', 12);
+        observer.set("pair3", 48665164, 'A', 30);
+        observer.set("pair3", 48665175, 'C', 30);
+        observer.set("pair3", 48665175, 'T', 30);
+        observer.set("pair3", 48665182, 'G', 30);
+        let counts = observer.count();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(*counts.get(&ReadHaplotype::from_string("ACT")).unwrap(), 1);
+        assert_eq!(*counts.get(&ReadHaplotype::from_string("ACG")).unwrap(), 1);
+        assert_eq!(observer.discarded(), 1);
+        assert_eq!(observer.rejections().mate_conflict, 1);
+    }
 }
@@ -10,11 +10,15 @@
 
 extern crate csv;
 
+use crate::alignments::ContigAliases;
 use crate::definition::AlleleDefinition;
+use crate::error::{Error, Result};
+use crate::read::ReadHaplotype;
+use crate::reference::ReferenceFasta;
 use csv::ReaderBuilder;
 use std::collections::hash_map;
 use std::collections::HashMap;
-use std::error::Error;
+use std::fmt;
 use std::path::PathBuf;
 
 /// A map of microhap names/identifiers to corresponding allele definitions.
@@ -23,40 +27,182 @@ pub struct MicrohapPanel {
 }
 
 impl MicrohapPanel {
-    pub fn from_csv(csv_path: &PathBuf) -> Result<MicrohapPanel, Box<dyn Error>> {
-        let mut reader = ReaderBuilder::new().from_path(csv_path)?;
+    /// Load allele definitions from a CSV file with one row per ADS: marker name, chromosome, and
+    /// 0-based coordinate.
+    pub fn from_csv(csv_path: &PathBuf) -> Result<MicrohapPanel> {
+        let mut reader = ReaderBuilder::new()
+            .from_path(csv_path)
+            .map_err(|e| Error::csv_file(csv_path, e))?;
         let mut definitions = HashMap::new();
         for result in reader.records() {
-            let record = result?;
-            let identifier = &record[0];
-            let chrom = &record[1];
-            let offset = record[2].parse::<u32>()?;
+            let record = result.map_err(|e| Error::csv_file(csv_path, e))?;
+            let position = record.position();
+            let (identifier, chrom, offset) = match (record.get(0), record.get(1), record.get(2)) {
+                (Some(identifier), Some(chrom), Some(offset)) => (identifier, chrom, offset),
+                _ => return Err(Error::csv(csv_path, position, "expected 3 fields")),
+            };
+            let offset = offset.parse::<u32>().map_err(|_| {
+                Error::csv(
+                    csv_path,
+                    position,
+                    &format!("invalid ADS coordinate '{}'", offset),
+                )
+            })?;
             let definition = definitions
                 .entry(identifier.to_owned())
-                .or_insert_with(|| AlleleDefinition::new(&chrom));
+                .or_insert_with(|| AlleleDefinition::new(chrom));
+            if definition.chromosome != chrom {
+                let message = format!(
+                    "marker {} has ADSs on multiple chromosomes ({} and {})",
+                    identifier, definition.chromosome, chrom
+                );
+                return Err(Error::csv(csv_path, position, &message));
+            }
             definition.add_snp_offset(offset);
         }
         Ok(MicrohapPanel { definitions })
     }
 
-    pub fn iter(&self) -> hash_map::Iter<String, AlleleDefinition> {
-        self.definitions.iter()
+    /// Number of microhaps in the panel.
+    pub fn len(&self) -> usize {
+        self.definitions.len()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Indicate whether the panel contains no microhaps.
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+
+    /// Allele definition for the given microhap, if present.
+    pub fn get(&self, identifier: &str) -> Option<&AlleleDefinition> {
+        self.definitions.get(identifier)
+    }
+
+    /// Iterate over microhap names/identifiers and corresponding allele definitions, in arbitrary
+    /// order.
+    pub fn iter(&self) -> hash_map::Iter<'_, String, AlleleDefinition> {
+        self.definitions.iter()
+    }
 
-    impl MicrohapPanel {
-        pub fn len(&self) -> usize {
-            self.definitions.len()
+    /// Look up the reference allele of every microhap in the given reference FASTA, whose sequence
+    /// names may differ from the chromosome names in the allele definitions as mapped by `aliases`.
+    /// Returns warnings, sorted by marker, for microhaps whose reference allele is unknown because
+    /// the chromosome or an ADS is absent from the reference, and for ADSs where the reference base
+    /// is N; any other ambiguous base is reported as is.
+    pub fn load_reference(
+        &mut self,
+        reference: &ReferenceFasta,
+        aliases: &ContigAliases,
+    ) -> Result<Vec<PanelIssue>> {
+        let reader = reference.reader()?;
+        let mut issues = Vec::new();
+        let mut mhids: Vec<String> = self.definitions.keys().cloned().collect();
+        mhids.sort();
+        for mhid in mhids {
+            let definition = self.definitions.get_mut(&mhid).unwrap();
+            let contig = aliases.resolve(&definition.chromosome);
+            let bases = reference.bases(&reader, contig, definition.get_offsets())?;
+            let mut report = |message: String| {
+                issues.push(PanelIssue {
+                    marker: mhid.clone(),
+                    severity: Severity::Warning,
+                    message,
+                })
+            };
+            match &bases {
+                None => report(format!(
+                    "reference allele unknown; {} or an ADS coordinate not found in reference",
+                    contig
+                )),
+                Some(bases) => {
+                    for (base, offset) in bases.chars().zip(definition.get_offsets()) {
+                        if base == 'N' {
+                            report(format!("reference base at ADS {} is N", offset));
+                        }
+                    }
+                }
+            }
+            definition.reference = bases.as_deref().map(ReadHaplotype::from_string);
         }
+        Ok(issues)
+    }
 
-        pub fn get(&self, identifier: &str) -> Option<&AlleleDefinition> {
-            self.definitions.get(identifier)
+    /// Check the allele definitions for problems that would prevent or undermine typing, sorted
+    /// by marker. If a reference FASTA is provided, every chromosome must be present in the
+    /// reference and every ADS must fall within the chromosome.
+    pub fn validate(&self, reference: Option<&ReferenceFasta>) -> Vec<PanelIssue> {
+        let mut issues = Vec::new();
+        let mut mhids: Vec<&String> = self.definitions.keys().collect();
+        mhids.sort();
+        for mhid in mhids {
+            let definition = &self.definitions[mhid];
+            let mut report = |severity: Severity, message: String| {
+                issues.push(PanelIssue {
+                    marker: mhid.clone(),
+                    severity,
+                    message,
+                })
+            };
+            if definition.has_duplicate_snps() {
+                report(Severity::Error, "duplicate ADS coordinates".to_string());
+            }
+            if definition.num_snps() < 2 {
+                report(Severity::Warning, "fewer than two ADSs".to_string());
+            }
+            if let Some(reference) = reference {
+                match reference.length(&definition.chromosome) {
+                    None => report(
+                        Severity::Error,
+                        format!(
+                            "chromosome {} not found in reference",
+                            definition.chromosome
+                        ),
+                    ),
+                    Some(length) if definition.end() as u64 >= length => report(
+                        Severity::Error,
+                        format!(
+                            "ADS coordinate {} beyond end of {} (length {})",
+                            definition.end(),
+                            definition.chromosome,
+                            length
+                        ),
+                    ),
+                    Some(_) => (),
+                }
+            }
         }
+        issues
+    }
+}
+
+/// Severity of a problem found while validating a panel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with the allele definition of a single marker.
+#[derive(Clone, Debug)]
+pub struct PanelIssue {
+    pub marker: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for PanelIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}\t{}\t{}", severity, self.marker, self.message)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn test_panel_basic() {
@@ -75,4 +221,83 @@ mod tests {
         let id_def_pairs: Vec<(&String, &AlleleDefinition)> = panel.iter().collect();
         assert_eq!(id_def_pairs.len(), 3);
     }
+
+    #[test]
+    fn test_panel_validate() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv"))
+            .expect("issue parsing panel CSV");
+        assert!(panel.validate(None).is_empty());
+        let reference = ReferenceFasta::from_path(&PathBuf::from("testdata/mwgfour-chr3.fa"))
+            .expect("issue loading reference");
+        let issues = panel.validate(Some(&reference));
+        assert_eq!(issues.len(), 4);
+        assert!(issues.iter().all(|issue| issue.severity == Severity::Error));
+        assert_eq!(
+            issues[0].to_string(),
+            "error\tmh03USC-3qC.v2\tADS coordinate 196653121 beyond end of chr3 (length 2000)"
+        );
+        assert_eq!(
+            issues[1].to_string(),
+            "error\tmh04WL-052.v1\tchromosome chr4 not found in reference"
+        );
+    }
+
+    #[test]
+    fn test_panel_load_reference() {
+        let mut panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour-chr3-refn.csv"))
+            .expect("issue parsing panel CSV");
+        let reference = ReferenceFasta::from_path(&PathBuf::from("testdata/mwgfour-chr3.fa"))
+            .expect("issue loading reference");
+        let issues = panel
+            .load_reference(&reference, &ContigAliases::default())
+            .unwrap();
+        let issues: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+        let expected = vec![
+            "warning\tmh03-ambiguous\treference base at ADS 400 is N",
+            "warning\tmh03-beyond\treference allele unknown; chr3 or an ADS coordinate not found \
+             in reference",
+        ];
+        assert_eq!(issues, expected);
+        let definition = panel.get("mh03USC-3qC.v2").unwrap();
+        let expected = ReadHaplotype::from_string("CTACTGG");
+        assert_eq!(definition.reference, Some(expected));
+        let definition = panel.get("mh03-ambiguous").unwrap();
+        assert_eq!(definition.reference, Some(ReadHaplotype::from_string("NC")));
+        assert!(panel.get("mh03-beyond").unwrap().reference.is_none());
+    }
+
+    #[test]
+    fn test_panel_validate_duplicates() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/badpanel.csv"))
+            .expect("issue parsing panel CSV");
+        let issues: Vec<String> = panel.validate(None).iter().map(|i| i.to_string()).collect();
+        let expected = vec![
+            "error\tmh01-dup\tduplicate ADS coordinates",
+            "warning\tmh02-single\tfewer than two ADSs",
+        ];
+        assert_eq!(issues, expected);
+    }
+
+    #[test]
+    fn test_panel_multiple_chromosomes() {
+        let result = MicrohapPanel::from_csv(&PathBuf::from("testdata/badpanel-chrom.csv"));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/badpanel-chrom.csv, line 3: marker mh01-split has ADSs on multiple \
+             chromosomes (chr1 and chr2)"
+        );
+    }
+
+    #[test]
+    fn test_panel_bad_coordinate() {
+        let result = MicrohapPanel::from_csv(&PathBuf::from("testdata/badpanel-offset.csv"));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/badpanel-offset.csv, line 3: invalid ADS coordinate '1020x'"
+        );
+        let result = MicrohapPanel::from_csv(&PathBuf::from("testdata/bogus.csv"));
+        assert_eq!(result.err().unwrap().exit_code(), 3);
+    }
 }
@@ -8,68 +8,158 @@
 // Development Center.
 // -------------------------------------------------------------------------------------------------
 
-use crate::thresholds::{AnalyticalThreshold, DetectionThreshold};
-use csv::ReaderBuilder;
-use std::path::PathBuf;
+use crate::caller::{CallerKind, NeighborRatio};
+use crate::error::{Error, Result};
+use crate::thresholds::{
+    AnalyticalThreshold, DetectionThreshold, MappingQualityThreshold, ThresholdRounding,
+};
+use csv::{ReaderBuilder, StringRecord};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 /// Parameters for configuring the haplotype calling algorithm.
 pub struct TypingParameters {
+    /// Genotype calling algorithm.
+    pub caller: CallerKind,
+    /// Minimum read count for an allele to be called, per marker.
     pub detection_threshold: DetectionThreshold,
+    /// Minimum allele frequency, as a fraction of the reads passing the detection threshold, per
+    /// marker.
     pub analytical_threshold: AnalyticalThreshold,
+    /// Rule for comparing read counts against the analytical threshold.
+    pub rounding: ThresholdRounding,
+    /// Maximum read count ratio of an allele to a neighbor one substitution away for the allele to
+    /// be rejected as a sequencing error; disabled if `None`.
+    pub neighbor_ratio: Option<NeighborRatio>,
+    /// Minimum base quality at an ADS for a read to contribute an allele.
     pub min_base_quality: u8,
+    /// Minimum mapping quality for a read to contribute a haplotype, per marker.
+    pub min_mapping_quality: MappingQualityThreshold,
+    /// Maximum number of reads observed at each ADS.
     pub max_depth: u32,
+    /// Combine the two mates of each read pair into a single fragment haplotype.
+    pub paired: bool,
+    /// Fail, rather than skip the affected markers, if a chromosome is absent from the alignments.
+    pub strict_contigs: bool,
+    /// Limits beyond which a typing result is flagged for review.
+    pub qc: QcLimits,
+}
+
+/// Limits used to flag typing results that warrant review. A result is flagged when fewer than
+/// `min_coverage` reads contribute a complete haplotype, when more than `max_alleles` alleles are
+/// called, when the minor-to-major read count ratio of a two-allele call falls below
+/// `min_het_balance`, or when more than `max_discard_rate` of the reads with an observed haplotype
+/// are discarded as partial. A profile is flagged as a likely mixture when at least
+/// `min_mixture_markers` markers have more than two alleles called.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QcLimits {
+    pub min_coverage: usize,
+    pub max_alleles: usize,
+    pub min_het_balance: f64,
+    pub max_discard_rate: f64,
+    pub min_mixture_markers: usize,
+}
+
+impl Default for QcLimits {
+    fn default() -> QcLimits {
+        QcLimits {
+            min_coverage: 20,
+            max_alleles: 2,
+            min_het_balance: 0.5,
+            max_discard_rate: 0.25,
+            min_mixture_markers: 2,
+        }
+    }
 }
 
 impl TypingParameters {
+    /// Parameters matching the command-line defaults.
     pub fn defaults() -> TypingParameters {
         TypingParameters {
+            caller: CallerKind::default(),
             detection_threshold: DetectionThreshold::new(10),
             analytical_threshold: AnalyticalThreshold::new(0.04),
+            rounding: ThresholdRounding::default(),
+            neighbor_ratio: None,
             min_base_quality: 10,
+            min_mapping_quality: MappingQualityThreshold::new(0),
             max_depth: 1e6 as u32,
+            paired: false,
+            strict_contigs: false,
+            qc: QcLimits::default(),
         }
     }
 
+    /// Initialize with panel-wide defaults, optionally overridden for individual markers by the
+    /// thresholds in a CSV file.
     pub fn new(
         detection_default: u16,
         analytical_default: f64,
         min_base_quality: u8,
+        min_mapq_default: u8,
         max_depth: u32,
         thresholds_file: Option<&PathBuf>,
-    ) -> TypingParameters {
+    ) -> Result<TypingParameters> {
         let mut params = TypingParameters {
+            caller: CallerKind::default(),
             detection_threshold: DetectionThreshold::new(detection_default),
             analytical_threshold: AnalyticalThreshold::new(analytical_default),
+            rounding: ThresholdRounding::default(),
+            neighbor_ratio: None,
             min_base_quality,
+            min_mapping_quality: MappingQualityThreshold::new(min_mapq_default),
             max_depth,
+            paired: false,
+            strict_contigs: false,
+            qc: QcLimits::default(),
         };
         match thresholds_file {
             None => (),
-            Some(csv_path) => params.parse_thresholds_csv(csv_path),
+            Some(csv_path) => params.parse_thresholds_csv(csv_path)?,
         };
 
-        params
+        Ok(params)
     }
 
-    fn parse_thresholds_csv(&mut self, csv_path: &PathBuf) {
+    /// Parse marker-specific thresholds from a CSV file. The detection and analytical thresholds
+    /// are required; an optional fourth column specifies the minimum mapping quality.
+    fn parse_thresholds_csv(&mut self, csv_path: &PathBuf) -> Result<()> {
         let mut reader = ReaderBuilder::new()
+            .flexible(true)
             .from_path(csv_path)
-            .expect("error parsing CSV file");
+            .map_err(|e| Error::csv_file(csv_path, e))?;
         for result in reader.records() {
-            let record = result.expect("error parsing CSV record");
-            let marker = &record[0];
-            let static_th = record[1]
-                .parse::<u16>()
-                .expect("error parsing detection threshold");
-            let dynamic_th = record[2]
-                .parse::<f64>()
-                .expect("error parsing analytical threshold");
+            let record = result.map_err(|e| Error::csv_file(csv_path, e))?;
+            let field = |index: usize, name: &str| match record.get(index) {
+                Some(value) => Ok(value),
+                None => Err(Error::csv(
+                    csv_path,
+                    record.position(),
+                    &format!("missing {}", name),
+                )),
+            };
+            let marker = field(0, "marker name")?;
+            let static_th = parse_field(csv_path, &record, field(1, "detection threshold")?)?;
+            let dynamic_th = parse_field(csv_path, &record, field(2, "analytical threshold")?)?;
             self.detection_threshold.insert(marker, static_th);
             self.analytical_threshold.insert(marker, dynamic_th);
+            if let Some(mapq) = record.get(3).filter(|field| !field.is_empty()) {
+                let mapq_th = parse_field(csv_path, &record, mapq)?;
+                self.min_mapping_quality.insert(marker, mapq_th);
+            }
         }
+        Ok(())
     }
 }
 
+/// Parse a numeric threshold from a field of the given CSV record.
+fn parse_field<T: FromStr>(csv_path: &Path, record: &StringRecord, value: &str) -> Result<T> {
+    value.parse::<T>().map_err(|_| {
+        let message = format!("invalid threshold '{}'", value);
+        Error::csv(csv_path, record.position(), &message)
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -78,6 +168,7 @@ mod tests {
     fn test_typing_parameters_defaults() {
         let params = TypingParameters::defaults();
         assert_eq!(params.min_base_quality, 10);
+        assert_eq!(params.min_mapping_quality.get("mh13KK-221.v1"), 0);
         assert_eq!(params.max_depth, 1000000);
         assert_eq!(params.detection_threshold.get("mh13KK-221.v1"), 10);
         assert_eq!(params.analytical_threshold.get("mh13KK-221.v1"), 0.04);
@@ -85,8 +176,9 @@ mod tests {
 
     #[test]
     fn test_typing_parameters_basic() {
-        let params = TypingParameters::new(15, 0.032, 20, 50000, None);
+        let params = TypingParameters::new(15, 0.032, 20, 30, 50000, None).unwrap();
         assert_eq!(params.min_base_quality, 20);
+        assert_eq!(params.min_mapping_quality.get("mh13KK-221.v1"), 30);
         assert_eq!(params.max_depth, 50000);
         assert_eq!(params.detection_threshold.get("mh13KK-221.v1"), 15);
         assert_eq!(params.analytical_threshold.get("mh13KK-221.v1"), 0.032);
@@ -95,7 +187,7 @@ mod tests {
     #[test]
     fn test_typing_parameters_csv() {
         let csv = PathBuf::from("testdata/mwgfour-thresholds.csv");
-        let params = TypingParameters::new(12, 0.024, 16, 64000, Some(&csv));
+        let params = TypingParameters::new(12, 0.024, 16, 0, 64000, Some(&csv)).unwrap();
         assert_eq!(params.min_base_quality, 16);
         assert_eq!(params.max_depth, 64000);
         assert_eq!(params.detection_threshold.get("mh03USC-3qC.v2"), 10);
@@ -109,4 +201,26 @@ mod tests {
         assert_eq!(params.detection_threshold.get("mh05KK-170.v3"), 12);
         assert_eq!(params.analytical_threshold.get("mh05KK-170.v3"), 0.024);
     }
+
+    #[test]
+    fn test_typing_parameters_csv_mapq() {
+        let csv = PathBuf::from("testdata/mwgfour-thresholds-mapq.csv");
+        let params = TypingParameters::new(12, 0.024, 16, 20, 64000, Some(&csv)).unwrap();
+        assert_eq!(params.min_mapping_quality.get("mh03USC-3qC.v2"), 20);
+        assert_eq!(params.min_mapping_quality.get("mh04WL-052.v1"), 50);
+        assert_eq!(params.min_mapping_quality.get("mh06SCUZJ-0528857"), 20);
+        assert_eq!(params.min_mapping_quality.get("mh17FHL-005.v3"), 0);
+        assert_eq!(params.detection_threshold.get("mh06SCUZJ-0528857"), 20);
+    }
+
+    #[test]
+    fn test_typing_parameters_csv_invalid() {
+        let csv = PathBuf::from("testdata/mwgfour-thresholds-bad.csv");
+        let result = TypingParameters::new(12, 0.024, 16, 20, 64000, Some(&csv));
+        let message = result.err().unwrap().to_string();
+        assert_eq!(
+            message,
+            "testdata/mwgfour-thresholds-bad.csv, line 3: invalid threshold 'ten'"
+        );
+    }
 }
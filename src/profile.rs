@@ -11,47 +11,137 @@
 extern crate serde;
 extern crate serde_json;
 
+use crate::error::{Error, Result};
 use crate::result::TypingResult;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
 
 /// Convenience data structure for serializing and deserializing typing results for a microhap panel
 /// to and from JSON.
 #[derive(Serialize, Deserialize)]
 pub struct MicrohapProfile {
     sample_id: String,
+    #[serde(default)]
+    mixture: MixtureSummary,
     #[serde(rename = "typing_results")]
     results: BTreeMap<String, TypingResult>,
 }
 
+/// Profile-level assessment of whether the sample contains DNA from more than one individual.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MixtureSummary {
+    /// Largest number of alleles called at any marker.
+    pub max_alleles: usize,
+    /// Minimum number of contributors needed to explain `max_alleles`, two alleles apiece.
+    pub min_contributors: usize,
+    /// Markers with more than two alleles called.
+    pub markers_over_two: Vec<String>,
+    /// Set when enough markers have more than two alleles called to suggest a mixture.
+    pub likely_mixture: bool,
+}
+
 impl MicrohapProfile {
+    /// Initialize an empty profile for the given sample.
     pub fn new(sample_id: &str) -> MicrohapProfile {
         MicrohapProfile {
             sample_id: sample_id.to_string(),
+            mixture: MixtureSummary::default(),
             results: BTreeMap::new(),
         }
     }
 
+    /// Add the typing result for a microhap, replacing any previous result for that microhap.
     pub fn add(&mut self, mhid: &str, result: TypingResult) {
         self.results.insert(mhid.to_string(), result);
     }
 
+    /// Serialize the profile to pretty-printed JSON.
     pub fn to_json(&self) -> String {
         serde_json::to_string_pretty(self).expect("Failed to serialize MicrohapProfile to JSON")
     }
+
+    /// Deserialize a profile from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Load a profile previously written in JSON format.
+    pub fn from_path(path: &Path) -> Result<MicrohapProfile> {
+        let data = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        serde_json::from_str(&data).map_err(|source| Error::Json {
+            path: Some(path.to_path_buf()),
+            source,
+        })
+    }
+
+    /// Load every profile in a JSON file holding either a single profile or an array of profiles,
+    /// such as those written by `--split-by` or `batch --combined`.
+    pub fn all_from_path(path: &Path) -> Result<Vec<MicrohapProfile>> {
+        let data = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let profiles = match data.trim_start().starts_with('[') {
+            true => serde_json::from_str(&data),
+            false => serde_json::from_str(&data).map(|profile| vec![profile]),
+        };
+        profiles.map_err(|source| Error::Json {
+            path: Some(path.to_path_buf()),
+            source,
+        })
+    }
+
+    /// Name/identifier of the sample that was typed.
+    pub fn sample_id(&self) -> &str {
+        &self.sample_id
+    }
+
+    /// Typing result for the given microhap, if present.
+    pub fn get(&self, mhid: &str) -> Option<&TypingResult> {
+        self.results.get(mhid)
+    }
+
+    /// Assess the typing results for evidence of a mixture, flagging the profile as a likely
+    /// mixture if at least `min_markers` markers have more than two alleles called.
+    pub fn summarize_mixture(&mut self, min_markers: usize) {
+        let max_alleles = self
+            .results
+            .values()
+            .map(|result| result.genotype.len())
+            .max()
+            .unwrap_or(0);
+        let markers_over_two: Vec<String> = self
+            .results
+            .iter()
+            .filter(|(_, result)| result.genotype.len() > 2)
+            .map(|(mhid, _)| mhid.clone())
+            .collect();
+        self.mixture = MixtureSummary {
+            max_alleles,
+            min_contributors: max_alleles.div_ceil(2),
+            likely_mixture: !markers_over_two.is_empty() && markers_over_two.len() >= min_markers,
+            markers_over_two,
+        };
+    }
+
+    /// Mixture assessment, as of the last call to `summarize_mixture`.
+    pub fn mixture(&self) -> &MixtureSummary {
+        &self.mixture
+    }
+
+    /// Iterate over microhap names/identifiers and corresponding typing results, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &TypingResult)> {
+        self.results.iter()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::thresholds::ThresholdRounding;
     use std::fs::File;
     use std::io::Read;
 
     impl MicrohapProfile {
-        pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
-            serde_json::from_str(json)
-        }
-
         pub fn from_file(path: &str) -> MicrohapProfile {
             let mut file = File::open(path).expect("Failed to open file");
             let mut data = String::new();
@@ -59,10 +149,6 @@ mod tests {
 
             MicrohapProfile::from_json(&data).expect("Failed to parse TypingResult from JSON")
         }
-
-        pub fn get(&self, mhid: &str) -> Option<&TypingResult> {
-            self.results.get(mhid)
-        }
     }
 
     #[test]
@@ -74,6 +160,55 @@ mod tests {
         assert_eq!(profile.results.len(), 1);
     }
 
+    #[test]
+    fn test_profile_mixture() {
+        let mut profile = MicrohapProfile::from_file("testdata/mwgfour-p1p3-profile.json");
+        profile.summarize_mixture(2);
+        let mixture = profile.mixture();
+        assert_eq!(mixture.max_alleles, 4);
+        assert_eq!(mixture.min_contributors, 2);
+        assert_eq!(
+            mixture.markers_over_two,
+            vec!["mh04WL-052.v1", "mh06SCUZJ-0528857", "mh17FHL-005.v3"]
+        );
+        assert!(mixture.likely_mixture);
+        profile.summarize_mixture(4);
+        assert!(!profile.mixture().likely_mixture);
+
+        let mut profile = MicrohapProfile::from_file("testdata/mwgfour-p1-profile.json");
+        profile.summarize_mixture(2);
+        assert_eq!(profile.mixture().max_alleles, 2);
+        assert_eq!(profile.mixture().min_contributors, 1);
+        assert!(profile.mixture().markers_over_two.is_empty());
+        assert!(!profile.mixture().likely_mixture);
+    }
+
+    #[test]
+    fn test_profile_from_path() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        assert_eq!(profile.sample_id(), "Item2");
+        assert_eq!(profile.iter().count(), 4);
+        let result = MicrohapProfile::from_path(Path::new("testdata/mwgfour.csv"));
+        let message = result.err().unwrap().to_string();
+        assert!(message.starts_with("testdata/mwgfour.csv: invalid JSON"));
+    }
+
+    #[test]
+    fn test_profile_all_from_path() {
+        let path = Path::new("testdata/mwgfour-multiplex-profiles.json");
+        let profiles = MicrohapProfile::all_from_path(path).unwrap();
+        let names: Vec<&str> = profiles.iter().map(|p| p.sample_id()).collect();
+        assert_eq!(names, vec!["Item1", "Item2"]);
+        assert_eq!(profiles[1].iter().count(), 4);
+        let path = Path::new("testdata/mwgfour-p2-profile.json");
+        let profiles = MicrohapProfile::all_from_path(path).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].sample_id(), "Item2");
+        let result = MicrohapProfile::all_from_path(Path::new("testdata/mwgfour.csv"));
+        assert!(matches!(result, Err(Error::Json { .. })));
+    }
+
     #[test]
     fn test_profile_json() {
         let profile = MicrohapProfile::from_file("testdata/mwgfour-p2-profile.json");
@@ -82,6 +217,7 @@ mod tests {
         let (mhid, result) = profile.results.iter().next().expect("iter fail");
         assert_eq!(mhid, "mh03USC-3qC.v2");
         assert_eq!(result.thresholds.analytical, 92.08);
+        assert_eq!(result.thresholds.rounding, ThresholdRounding::Floor);
         let json = profile.to_json();
         assert!(json.contains("mh06SCUZJ-0528857"));
         assert!(json.contains("\"ACCGGGCTC\": 1180,"));
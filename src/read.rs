@@ -40,9 +40,32 @@ impl ReadHaplotype {
         self.alleles[index] = allele;
     }
 
+    /// Allele observed at the given SNP index, if the index is valid.
+    pub fn get(&self, index: usize) -> Option<char> {
+        self.alleles.get(index).copied()
+    }
+
+    /// Iterate over the alleles at each SNP, in order.
+    pub fn iter(&self) -> impl Iterator<Item = char> + '_ {
+        self.alleles.iter().copied()
+    }
+
+    /// Number of SNPs at which this haplotype differs from another.
+    pub fn hamming_distance(&self, other: &ReadHaplotype) -> usize {
+        self.iter()
+            .zip(other.iter())
+            .filter(|(a, b)| a != b)
+            .count()
+    }
+
     /// Indicate whether the read haplotype is complete, i.e., whether any N characters remain.
     pub fn is_partial(&self) -> bool {
-        self.alleles.iter().any(|&a| a == 'N')
+        self.alleles.contains(&'N')
+    }
+
+    /// Indicate whether no alleles have been observed, i.e., whether only N characters are present.
+    pub fn is_empty(&self) -> bool {
+        self.alleles.iter().all(|&a| a == 'N')
     }
 }
 
@@ -79,10 +102,6 @@ mod tests {
     use super::*;
 
     impl ReadHaplotype {
-        pub fn get(&self, index: usize) -> Option<char> {
-            self.alleles.get(index).copied()
-        }
-
         pub fn len(&self) -> usize {
             self.alleles.len()
         }
@@ -91,9 +110,11 @@ mod tests {
     #[test]
     fn test_readhap_basic() {
         let mut hap = ReadHaplotype::new(3);
+        assert!(hap.is_empty());
         hap.set(0, 'C');
         hap.set(1, 'A');
         assert!(hap.is_partial());
+        assert!(!hap.is_empty());
         hap.set(2, 'T');
         assert!(!hap.is_partial());
         assert_eq!(hap.to_string(), "CAT");
@@ -110,6 +131,20 @@ mod tests {
         assert_eq!(hap.to_string(), "GATTACA");
     }
 
+    #[test]
+    fn test_readhap_hamming_distance() {
+        let hap = ReadHaplotype::from_string("GATTACA");
+        assert_eq!(hap.hamming_distance(&hap), 0);
+        assert_eq!(
+            hap.hamming_distance(&ReadHaplotype::from_string("GACTACA")),
+            1
+        );
+        assert_eq!(
+            hap.hamming_distance(&ReadHaplotype::from_string("CATTACT")),
+            2
+        );
+    }
+
     #[test]
     #[should_panic(expected = "index error: 42")]
     fn test_readhap_seq_bad_index() {
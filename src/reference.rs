@@ -0,0 +1,161 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate rust_htslib;
+
+use crate::error::{Error, Result};
+use rust_htslib::bam::HeaderView;
+use rust_htslib::faidx;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Reference genome sequence in FASTA format, indexed with `samtools faidx`.
+pub struct ReferenceFasta {
+    path: PathBuf,
+    lengths: HashMap<String, u64>,
+}
+
+impl ReferenceFasta {
+    /// Load the names and lengths of the reference sequences from the FASTA index (`.fai`).
+    pub fn from_path(path: &Path) -> Result<ReferenceFasta> {
+        fs::metadata(path).map_err(|e| Error::io(path, e))?;
+        let mut fai_path = path.as_os_str().to_owned();
+        fai_path.push(".fai");
+        let fai_path = PathBuf::from(fai_path);
+        let index = fs::read_to_string(&fai_path).map_err(|_| {
+            Error::Validation(format!(
+                "reference FASTA index {} does not exist; run `samtools faidx {}`",
+                fai_path.display(),
+                path.display()
+            ))
+        })?;
+        let mut lengths = HashMap::new();
+        for line in index.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let length = match fields.get(1).map(|field| field.parse::<u64>()) {
+                Some(Ok(length)) => length,
+                _ => {
+                    let message = format!("malformed FASTA index line '{}'", line);
+                    return Err(Error::csv(&fai_path, None, &message));
+                }
+            };
+            lengths.insert(fields[0].to_string(), length);
+        }
+        Ok(ReferenceFasta {
+            path: path.to_path_buf(),
+            lengths,
+        })
+    }
+
+    /// Length of the named reference sequence, if present.
+    pub fn length(&self, contig: &str) -> Option<u64> {
+        self.lengths.get(contig).copied()
+    }
+
+    /// Open the FASTA for reading sequence with `bases`.
+    pub fn reader(&self) -> Result<faidx::Reader> {
+        faidx::Reader::from_path(&self.path).map_err(|e| Error::io(&self.path, io::Error::other(e)))
+    }
+
+    /// Reference bases at the given (0-based, ascending) coordinates of the named sequence, in
+    /// upper case, read with a `reader` opened on this FASTA, or `None` if the sequence is absent
+    /// from the reference or a coordinate is beyond its end.
+    pub fn bases(
+        &self,
+        reader: &faidx::Reader,
+        contig: &str,
+        offsets: &[u32],
+    ) -> Result<Option<String>> {
+        let (start, end) = match (offsets.first(), offsets.last(), self.length(contig)) {
+            (Some(&start), Some(&end), Some(length)) if (end as u64) < length => {
+                (start as usize, end as usize)
+            }
+            (None, _, Some(_)) => return Ok(Some(String::new())),
+            _ => return Ok(None),
+        };
+        let sequence = reader
+            .fetch_seq(contig, start, end)
+            .map_err(|e| Error::io(&self.path, io::Error::other(e)))?;
+        let bases = offsets
+            .iter()
+            .map(|&offset| match sequence.get(offset as usize - start) {
+                Some(base) => base.to_ascii_uppercase() as char,
+                None => 'N',
+            })
+            .collect();
+        Ok(Some(bases))
+    }
+
+    /// Verify that every sequence declared in an alignment file header is present in the reference
+    /// with the same length.
+    pub fn check_header(&self, header: &HeaderView) -> Result<()> {
+        for tid in 0..header.target_count() {
+            let contig = String::from_utf8_lossy(header.tid2name(tid));
+            let expected = header.target_len(tid).unwrap_or(0);
+            match self.length(&contig) {
+                None => {
+                    return Err(Error::Validation(format!(
+                        "sequence '{}' in alignment header not found in reference FASTA {}",
+                        contig,
+                        self.path.display()
+                    )))
+                }
+                Some(length) if length != expected => {
+                    return Err(Error::Validation(format!(
+                    "sequence '{}' has length {} in alignment header but {} in reference FASTA {}",
+                    contig,
+                    expected,
+                    length,
+                    self.path.display()
+                )))
+                }
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_lengths() {
+        let reference = ReferenceFasta::from_path(&PathBuf::from("testdata/mwgfour-chr3.fa"))
+            .expect("issue loading reference");
+        assert_eq!(reference.length("chr3"), Some(2000));
+        assert_eq!(reference.length("3"), None);
+    }
+
+    #[test]
+    fn test_reference_bases() {
+        let reference = ReferenceFasta::from_path(&PathBuf::from("testdata/mwgfour-chr3.fa"))
+            .expect("issue loading reference");
+        let reader = reference.reader().unwrap();
+        let offsets = [865, 971, 1025, 1034, 1044, 1084, 1121];
+        let bases = reference.bases(&reader, "chr3", &offsets).unwrap();
+        assert_eq!(bases.as_deref(), Some("CTACTGG"));
+        let bases = reference.bases(&reader, "chr3", &[0, 1999]).unwrap();
+        assert_eq!(bases.as_deref(), Some("NN"));
+        let bases = reference.bases(&reader, "chr3", &[865, 2000]).unwrap();
+        assert_eq!(bases, None);
+        assert_eq!(reference.bases(&reader, "chr4", &[865]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_reference_missing() {
+        let result = ReferenceFasta::from_path(&PathBuf::from("testdata/bogus.fa"));
+        let message = result.err().unwrap().to_string();
+        assert!(message.starts_with("could not access testdata/bogus.fa"));
+    }
+}
@@ -13,20 +13,115 @@ extern crate serde;
 extern crate serde_json;
 
 use crate::counter::ReadHapCounter;
+use crate::error::Result;
 use crate::read::ReadHaplotype;
 use crate::thresholds::TypingThresholds;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Typing result for a single microhap.
 #[derive(Serialize, Deserialize)]
 pub struct TypingResult {
+    #[serde(default)]
+    pub status: MarkerStatus,
     pub genotype: Vec<ReadHaplotype>,
+    /// Reference haplotype, if the allele definitions were loaded with a reference FASTA.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<ReadHaplotype>,
     pub coverage: TypingCoverage,
     pub num_discarded: usize,
+    #[serde(default)]
+    pub rejections: ReadRejections,
     pub thresholds: TypingThresholds,
+    #[serde(default)]
+    pub error_neighbors: BTreeMap<ReadHaplotype, ReadHaplotype>,
+    #[serde(default)]
+    pub het_balance: Option<f64>,
+    #[serde(default)]
+    pub frequencies: BTreeMap<ReadHaplotype, AlleleFrequency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub likelihood: Option<GenotypeLikelihoods>,
+    #[serde(default)]
+    pub qc_flags: Vec<QcFlag>,
     pub counts: ReadHapCounter,
 }
 
+impl TypingResult {
+    /// Serialize the typing result to pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize TypingResult to JSON")
+    }
+
+    /// Deserialize a typing result from JSON.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Genotype call as a slash-separated list of alleles, e.g. `CCACTGG/CTACTGG`.
+    pub fn genotype_string(&self) -> String {
+        self.genotype
+            .iter()
+            .map(|allele| allele.to_string())
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+
+    /// Indicate whether the reference haplotype is known and is the only allele called.
+    pub fn is_homozygous_reference(&self) -> bool {
+        match &self.reference {
+            Some(reference) => self.genotype.len() == 1 && self.genotype[0] == *reference,
+            None => false,
+        }
+    }
+}
+
+/// Indicates whether reads were available for typing a single microhap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerStatus {
+    /// Reads were observed at the marker.
+    #[default]
+    Ok,
+    /// The marker's chromosome is present in the alignments, but no reads overlap the marker.
+    NoReads,
+    /// The marker's chromosome is absent from the alignment header, so the marker was not typed.
+    ContigNotFound,
+}
+
+/// Quality control concern raised for a single typing result; see `QcLimits`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QcFlag {
+    /// Too few reads contribute a complete haplotype.
+    LowCoverage,
+    /// More alleles are called than expected for a single-source sample.
+    PossibleMixture,
+    /// The two alleles of a heterozygous call have very unequal read counts.
+    HeterozygoteImbalance,
+    /// A large fraction of reads are discarded as partial haplotypes.
+    HighDiscardRate,
+    /// No allele passes the detection and analytical thresholds.
+    NoAllelesPassing,
+}
+
+/// Read frequency of a single observed allele.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlleleFrequency {
+    /// Allele read count as a fraction of the reads supporting called alleles.
+    pub passing: f64,
+    /// Allele read count as a fraction of all reads spanning every ADS.
+    pub spanning: f64,
+}
+
+/// Genotype likelihoods computed by the likelihood caller; see `CallerKind`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GenotypeLikelihoods {
+    /// Phred-scaled probability that the called genotype is wrong, capped at 99.
+    pub genotype_quality: u32,
+    /// Log10 likelihood of each candidate genotype, keyed by its two slash-separated alleles.
+    pub log10_likelihoods: BTreeMap<String, f64>,
+}
+
 /// Read coverage statistics for a single typing result.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TypingCoverage {
@@ -35,6 +130,20 @@ pub struct TypingCoverage {
     pub min: u32,
 }
 
+/// Number of reads overlapping a single microhap that did not contribute a complete haplotype,
+/// broken down by the reason each read was rejected.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReadRejections {
+    pub flags: usize,
+    pub mapping_quality: usize,
+    pub base_quality: usize,
+    pub gap: usize,
+    pub partial_span: usize,
+    pub depth_capped: usize,
+    pub mate_conflict: usize,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,14 +154,6 @@ mod tests {
     use std::io::Read;
 
     impl TypingResult {
-        pub fn to_json(&self) -> String {
-            serde_json::to_string_pretty(self).expect("Failed to serialize TypingResult to JSON")
-        }
-
-        pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
-            serde_json::from_str(json)
-        }
-
         pub fn from_file(path: &str) -> TypingResult {
             let mut file = File::open(path).expect("Failed to open file");
             let mut data = String::new();
@@ -79,9 +180,15 @@ mod tests {
     #[test]
     fn test_typing_result_basic() {
         let mut caller = init_caller();
-        let result = caller.apply_filters(2, 0.02);
+        let mut result = caller.apply_filters(2, 0.02);
         let readhap = ReadHaplotype::from_string("AATAT");
-        assert_eq!(result.genotype, vec![readhap]);
+        assert_eq!(result.genotype, vec![readhap.clone()]);
+        assert_eq!(result.het_balance, None);
+        assert!(!result.is_homozygous_reference());
+        result.reference = Some(readhap);
+        assert!(result.is_homozygous_reference());
+        result.reference = Some(ReadHaplotype::from_string("ACGAT"));
+        assert!(!result.is_homozygous_reference());
     }
 
     #[test]
@@ -89,6 +196,7 @@ mod tests {
         let result = TypingResult::from_file("testdata/dummy-result.json");
         assert_eq!(result.coverage.max, 2478);
         assert_eq!(result.counts.tally.len(), 10);
+        assert_eq!(result.genotype_string(), "AGTTTT/GCTTCT");
         let json = result.to_json();
         assert!(json.contains("\"num_discarded\": 95,"));
         assert!(json.contains("\"GCTTCT\": 1184,\n    \"GGTTTT\": 1,"));
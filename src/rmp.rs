@@ -0,0 +1,335 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+use crate::error::{Error, Result};
+use crate::frequencies::FrequencyTable;
+use crate::profile::MicrohapProfile;
+use crate::read::ReadHaplotype;
+use std::collections::BTreeMap;
+
+/// Parameters for converting allele frequencies into genotype frequencies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PopulationModel {
+    /// Coancestry coefficient (FST) correcting for population substructure.
+    pub theta: f64,
+    /// Frequency assigned to alleles absent from (or rarer than this in) the frequency table.
+    pub min_frequency: f64,
+}
+
+impl Default for PopulationModel {
+    fn default() -> PopulationModel {
+        PopulationModel {
+            theta: 0.01,
+            min_frequency: 0.001,
+        }
+    }
+}
+
+impl PopulationModel {
+    /// Initialize with the given coancestry coefficient, which must be in [0, 1), and minimum
+    /// allele frequency, which must be in (0, 1].
+    pub fn new(theta: f64, min_frequency: f64) -> Result<PopulationModel> {
+        let model = PopulationModel {
+            theta,
+            min_frequency,
+        };
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Check that theta and the minimum allele frequency are within range.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..1.0).contains(&self.theta) {
+            let message = format!("theta {} outside the range [0, 1)", self.theta);
+            return Err(Error::Validation(message));
+        }
+        validate_min_frequency(self.min_frequency)
+    }
+
+    /// Frequency of the given allele at the given marker in the given population, raised to the
+    /// minimum frequency if the allele is absent from the table or rarer than the minimum.
+    pub fn allele_frequency(
+        &self,
+        table: &FrequencyTable,
+        population: &str,
+        marker: &str,
+        allele: &ReadHaplotype,
+    ) -> f64 {
+        allele_frequency(table, population, marker, allele, self.min_frequency)
+    }
+
+    /// Frequency of a genotype with the given allele frequencies (one for a homozygote, two for a
+    /// heterozygote), using the subpopulation correction of NRC II recommendation 4.10, or `None`
+    /// if given any other number of frequencies.
+    pub fn genotype_frequency(&self, frequencies: &[f64]) -> Option<f64> {
+        let theta = self.theta;
+        let denominator = (1.0 + theta) * (1.0 + 2.0 * theta);
+        let frequency = match frequencies {
+            [p] => (2.0 * theta + (1.0 - theta) * p) * (3.0 * theta + (1.0 - theta) * p),
+            [p, q] => 2.0 * (theta + (1.0 - theta) * p) * (theta + (1.0 - theta) * q),
+            _ => return None,
+        };
+        Some(frequency / denominator)
+    }
+}
+
+/// Check that a minimum allele frequency is in (0, 1].
+pub fn validate_min_frequency(min_frequency: f64) -> Result<()> {
+    if !(min_frequency > 0.0 && min_frequency <= 1.0) {
+        let message = format!(
+            "minimum frequency {} outside the range (0, 1]",
+            min_frequency
+        );
+        return Err(Error::Validation(message));
+    }
+    Ok(())
+}
+
+/// Frequency of the given allele at the given marker in the given population, raised to
+/// `min_frequency` if the allele is absent from the table or rarer than the minimum.
+pub fn allele_frequency(
+    table: &FrequencyTable,
+    population: &str,
+    marker: &str,
+    allele: &ReadHaplotype,
+    min_frequency: f64,
+) -> f64 {
+    let frequency = table.get(population, marker, allele).unwrap_or(0.0);
+    frequency.max(min_frequency)
+}
+
+/// Random match probability (RMP) of a single-source profile: the probability that an unrelated
+/// individual drawn at random from a population has the same genotype at every marker. The RMP is
+/// computed for each population in the frequency table as the product of the genotype frequencies
+/// at each marker. Markers with no alleles or more than two alleles called are excluded, as are
+/// markers absent from a population's frequencies.
+pub struct MatchProbability {
+    populations: Vec<String>,
+    markers: BTreeMap<String, (String, Vec<Option<f64>>)>,
+    skipped: Vec<String>,
+}
+
+impl MatchProbability {
+    /// Compute genotype frequencies for the given profile in the given populations, or in every
+    /// population in the table if none are specified. Fails if the model parameters are out of
+    /// range.
+    pub fn new(
+        profile: &MicrohapProfile,
+        table: &FrequencyTable,
+        populations: &[String],
+        model: &PopulationModel,
+    ) -> Result<MatchProbability> {
+        model.validate()?;
+        let populations: Vec<String> = match populations.is_empty() {
+            true => table.populations().cloned().collect(),
+            false => populations.to_vec(),
+        };
+        if let Some(missing) = populations.iter().find(|p| !table.has_population(p)) {
+            let message = format!("population {} absent from the frequency table", missing);
+            return Err(Error::Validation(message));
+        }
+        let mut markers = BTreeMap::new();
+        let mut skipped = Vec::new();
+        for (mhid, result) in profile.iter() {
+            if result.genotype.is_empty() || result.genotype.len() > 2 {
+                skipped.push(mhid.clone());
+                continue;
+            }
+            let frequencies = populations
+                .iter()
+                .map(|population| {
+                    if !table.has_marker(population, mhid) {
+                        return None;
+                    }
+                    let alleles: Vec<f64> = result
+                        .genotype
+                        .iter()
+                        .map(|allele| model.allele_frequency(table, population, mhid, allele))
+                        .collect();
+                    model.genotype_frequency(&alleles)
+                })
+                .collect();
+            markers.insert(mhid.clone(), (result.genotype_string(), frequencies));
+        }
+        Ok(MatchProbability {
+            populations,
+            markers,
+            skipped,
+        })
+    }
+
+    /// Populations for which the RMP was computed.
+    pub fn populations(&self) -> &[String] {
+        &self.populations
+    }
+
+    /// Markers excluded because no alleles or more than two alleles were called.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Genotype frequency at the given marker in the population with the given index, if the
+    /// marker was included and the population index is valid.
+    pub fn genotype_frequency(&self, marker: &str, population: usize) -> Option<f64> {
+        self.markers
+            .get(marker)
+            .and_then(|(_, frequencies)| frequencies.get(population).copied().flatten())
+    }
+
+    /// Combined RMP in the population with the given index, along with the number of markers
+    /// contributing to it, or `None` if the population index is invalid.
+    pub fn combined(&self, population: usize) -> Option<(f64, usize)> {
+        if population >= self.populations.len() {
+            return None;
+        }
+        let frequencies: Vec<f64> = self
+            .markers
+            .values()
+            .filter_map(|(_, frequencies)| frequencies[population])
+            .collect();
+        let log10_rmp: f64 = frequencies.iter().map(|f| f.log10()).sum();
+        Some((10f64.powf(log10_rmp), frequencies.len()))
+    }
+
+    /// Tab-separated table with one row per marker, giving the genotype and its frequency in each
+    /// population, followed by a row with the combined RMP in each population.
+    pub fn to_table(&self) -> String {
+        let mut table = format!("Marker\tGenotype\t{}\n", self.populations.join("\t"));
+        for (mhid, (genotype, frequencies)) in &self.markers {
+            let frequencies: Vec<String> = frequencies
+                .iter()
+                .map(|frequency| match frequency {
+                    Some(frequency) => format!("{:.4e}", frequency),
+                    None => "NA".to_string(),
+                })
+                .collect();
+            table.push_str(&format!(
+                "{}\t{}\t{}\n",
+                mhid,
+                genotype,
+                frequencies.join("\t")
+            ));
+        }
+        let combined: Vec<String> = (0..self.populations.len())
+            .filter_map(|i| self.combined(i))
+            .map(|(rmp, _)| format!("{:.4e}", rmp))
+            .collect();
+        table.push_str(&format!("Combined\t\t{}\n", combined.join("\t")));
+        table
+    }
+
+    /// Summary of the combined RMP in each population, one line per population.
+    pub fn summary(&self) -> String {
+        self.populations
+            .iter()
+            .enumerate()
+            .filter_map(|(i, population)| {
+                let (rmp, count) = self.combined(i)?;
+                Some(format!(
+                    "{}: RMP 1 in {:.4e} over {} markers",
+                    population,
+                    1.0 / rmp,
+                    count
+                ))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn load_table() -> FrequencyTable {
+        FrequencyTable::from_csv(Path::new("testdata/mwgfour-frequencies.csv")).unwrap()
+    }
+
+    #[test]
+    fn test_rmp_genotype_frequency() {
+        let model = PopulationModel {
+            theta: 0.0,
+            min_frequency: 0.001,
+        };
+        assert!((model.genotype_frequency(&[0.4]).unwrap() - 0.16).abs() < 1e-12);
+        assert!((model.genotype_frequency(&[0.3, 0.2]).unwrap() - 0.12).abs() < 1e-12);
+        assert_eq!(model.genotype_frequency(&[]), None);
+        assert_eq!(model.genotype_frequency(&[0.3, 0.2, 0.1]), None);
+        let model = PopulationModel::default();
+        let expected = 0.416 * 0.426 / (1.01 * 1.02);
+        assert!((model.genotype_frequency(&[0.4]).unwrap() - expected).abs() < 1e-12);
+        let expected = 2.0 * 0.307 * 0.208 / (1.01 * 1.02);
+        assert!((model.genotype_frequency(&[0.3, 0.2]).unwrap() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_rmp_model_range() {
+        assert!(PopulationModel::new(0.0, 1.0).is_ok());
+        let message = PopulationModel::new(-2.0, 0.001).err().unwrap().to_string();
+        assert_eq!(message, "theta -2 outside the range [0, 1)");
+        assert!(PopulationModel::new(1.0, 0.001).is_err());
+        let message = PopulationModel::new(0.0, 0.0).err().unwrap().to_string();
+        assert_eq!(message, "minimum frequency 0 outside the range (0, 1]");
+        assert!(PopulationModel::new(0.01, 1.5).is_err());
+        assert!(PopulationModel::new(f64::NAN, 0.001).is_err());
+
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let model = PopulationModel {
+            theta: 0.0,
+            min_frequency: 0.0,
+        };
+        let result = MatchProbability::new(&profile, &load_table(), &[], &model);
+        assert!(matches!(result, Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_rmp_profile() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p2-profile.json")).unwrap();
+        let model = PopulationModel {
+            theta: 0.0,
+            min_frequency: 0.01,
+        };
+        let rmp = MatchProbability::new(&profile, &load_table(), &[], &model).unwrap();
+        assert_eq!(rmp.populations(), ["AFR", "EUR"]);
+        let (combined, count) = rmp.combined(1).unwrap();
+        assert_eq!(count, 4);
+        assert!((combined - 0.12 * 0.075 * 0.04 * 0.12).abs() < 1e-12);
+        let unseen = rmp.genotype_frequency("mh06SCUZJ-0528857", 0).unwrap();
+        assert!((unseen - 2.0 * 0.3 * 0.01).abs() < 1e-12);
+        assert_eq!(rmp.genotype_frequency("mh17FHL-005.v3", 0), None);
+        assert_eq!(rmp.genotype_frequency("mh06SCUZJ-0528857", 2), None);
+        assert_eq!(rmp.combined(0).unwrap().1, 3);
+        assert_eq!(rmp.combined(2), None);
+        let table = rmp.to_table();
+        assert!(table.starts_with("Marker\tGenotype\tAFR\tEUR\n"));
+        assert!(table.contains("mh17FHL-005.v3\tAGTTTT/GCTTCT\tNA\t1.2000e-1\n"));
+        assert!(rmp
+            .summary()
+            .contains("EUR: RMP 1 in 2.3148e4 over 4 markers"));
+    }
+
+    #[test]
+    fn test_rmp_mixture_and_population() {
+        let profile =
+            MicrohapProfile::from_path(Path::new("testdata/mwgfour-p1p3-profile.json")).unwrap();
+        let populations = vec!["EUR".to_string()];
+        let model = PopulationModel::default();
+        let rmp = MatchProbability::new(&profile, &load_table(), &populations, &model).unwrap();
+        assert_eq!(rmp.skipped().len(), 3);
+        assert_eq!(rmp.combined(0).unwrap().1, 1);
+        let populations = vec!["ASN".to_string()];
+        let result = MatchProbability::new(&profile, &load_table(), &populations, &model);
+        let message = result.err().unwrap().to_string();
+        assert_eq!(message, "population ASN absent from the frequency table");
+    }
+}
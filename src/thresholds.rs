@@ -10,6 +10,7 @@
 
 extern crate serde;
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -19,8 +20,49 @@ pub struct TypingThresholds {
     pub dynamic: f64,
     pub analytical: f64,
     pub detection: u16,
+    /// Rule used to compare read counts against the analytical threshold. Results recorded before
+    /// this field was introduced were called with the `floor` rule.
+    #[serde(default = "ThresholdRounding::legacy")]
+    pub rounding: ThresholdRounding,
 }
 
+/// Rule for comparing an allele's read count against the analytical threshold, which is the
+/// product of a read count and a fraction and so is generally not a whole number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdRounding {
+    /// Read count must be at least the threshold itself, allowing for floating-point error in the
+    /// product
+    #[default]
+    Exact,
+    /// Threshold is rounded down before comparison, as in earlier versions of mhrs
+    Floor,
+    /// Threshold is rounded to the nearest whole number (halves up) before comparison
+    Round,
+}
+
+impl ThresholdRounding {
+    /// Rule applied to typing results that predate the choice of rounding rule.
+    pub fn legacy() -> ThresholdRounding {
+        ThresholdRounding::Floor
+    }
+
+    /// Indicate whether the given read count meets the threshold under this rule. Under the exact
+    /// rule, a count is considered equal to a threshold within a small relative tolerance, so that
+    /// 7 reads meet a threshold of 100 x 0.07 (7.000000000000001 in floating point).
+    pub fn passes(&self, count: usize, threshold: f64) -> bool {
+        let threshold = match self {
+            ThresholdRounding::Exact => threshold - THRESHOLD_TOLERANCE * threshold.max(1.0),
+            ThresholdRounding::Floor => threshold.floor(),
+            ThresholdRounding::Round => threshold.round(),
+        };
+        count as f64 >= threshold
+    }
+}
+
+/// Relative tolerance applied when comparing read counts to an exact analytical threshold.
+const THRESHOLD_TOLERANCE: f64 = 1e-9;
+
 /// Threshold map for an entire microhap panel.
 pub struct ReadCountThreshold<T> {
     default: T,
@@ -28,6 +70,7 @@ pub struct ReadCountThreshold<T> {
 }
 
 impl<T: Clone> ReadCountThreshold<T> {
+    /// Initialize with a default applied to all markers.
     pub fn new(default: T) -> ReadCountThreshold<T> {
         ReadCountThreshold {
             default,
@@ -35,10 +78,17 @@ impl<T: Clone> ReadCountThreshold<T> {
         }
     }
 
+    /// Replace the default, leaving any marker-specific overrides in place.
+    pub fn set_default(&mut self, default: T) {
+        self.default = default;
+    }
+
+    /// Override the default for a single marker.
     pub fn insert(&mut self, marker_id: &str, threshold: T) {
         self.by_marker.insert(marker_id.to_string(), threshold);
     }
 
+    /// Threshold for the given marker: its override if present, otherwise the default.
     pub fn get(&self, marker_id: &str) -> T {
         match self.by_marker.get(marker_id) {
             Some(threshold) => threshold.clone(),
@@ -49,6 +99,7 @@ impl<T: Clone> ReadCountThreshold<T> {
 
 pub type DetectionThreshold = ReadCountThreshold<u16>;
 pub type AnalyticalThreshold = ReadCountThreshold<f64>;
+pub type MappingQualityThreshold = ReadCountThreshold<u8>;
 
 #[cfg(test)]
 mod tests {
@@ -62,6 +113,9 @@ mod tests {
         assert_eq!(t.get("mh09USC-9qC"), 5);
         assert_eq!(t.get("mh10WL-031"), 15);
         assert_eq!(t.get("mh04SHY-004"), 42);
+        t.set_default(7);
+        assert_eq!(t.get("mh04SHY-004"), 7);
+        assert_eq!(t.get("mh10WL-031"), 15);
     }
 
     #[test]
@@ -73,4 +127,18 @@ mod tests {
         assert_eq!(t.get("mh19USC-19qB.v2"), 0.029);
         assert_eq!(t.get("mh09WL-034"), 0.042);
     }
+
+    #[test]
+    fn test_threshold_rounding() {
+        assert!(!ThresholdRounding::Exact.passes(92, 92.08));
+        assert!(ThresholdRounding::Exact.passes(93, 92.08));
+        assert!(ThresholdRounding::Exact.passes(7, 100.0 * 0.07));
+        assert!(ThresholdRounding::Exact.passes(7, 50.0 * 0.14));
+        assert!(!ThresholdRounding::Exact.passes(6, 100.0 * 0.07));
+        assert!(ThresholdRounding::Floor.passes(92, 92.08));
+        assert!(!ThresholdRounding::Floor.passes(91, 92.08));
+        assert!(ThresholdRounding::Round.passes(92, 92.08));
+        assert!(!ThresholdRounding::Round.passes(92, 92.5));
+        assert!(ThresholdRounding::Exact.passes(92, 92.0));
+    }
 }
@@ -0,0 +1,335 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+extern crate rust_htslib;
+
+use crate::alignments::AlignmentFile;
+use crate::definition::AlleleDefinition;
+use crate::error::{Error, Result};
+use crate::observer::HaplotypeObserver;
+use crate::panel::MicrohapPanel;
+use crate::parameters::TypingParameters;
+use rust_htslib::{bam, bam::Read};
+use std::collections::HashSet;
+use std::ops::Range;
+use std::thread;
+
+/// A cluster of overlapping markers, paired with the BAM header ID of their chromosome.
+type ClusterTask<'a> = (u32, &'a mut [(String, HaplotypeObserver)]);
+
+/// Data structure for observing haplotypes at every microhap in a panel with a single pass over a
+/// BAM file. Markers are sorted by chromosome and position, and markers with overlapping extents
+/// are grouped into clusters so that each pileup position is fetched only once and dispatched to
+/// every observer whose marker it touches.
+///
+/// For multiplexed alignments, each marker can instead be observed separately for each of several
+/// groups of read groups, yielding one set of observers per group from the same single pass.
+pub struct PanelTraversal {
+    observers: Vec<(String, HaplotypeObserver)>,
+    groups: usize,
+    missing_contigs: HashSet<String>,
+}
+
+impl PanelTraversal {
+    /// Initialize an empty observer for each microhap in the panel.
+    pub fn new(panel: &MicrohapPanel) -> PanelTraversal {
+        PanelTraversal::with_groups(panel, vec![None])
+    }
+
+    /// Initialize an empty observer for each microhap in the panel and each of the given groups of
+    /// read group IDs; each observer sees only the reads of its group.
+    pub fn split(panel: &MicrohapPanel, groups: &[HashSet<String>]) -> PanelTraversal {
+        let groups = groups.iter().cloned().map(Some).collect();
+        PanelTraversal::with_groups(panel, groups)
+    }
+
+    /// Initialize observers for each microhap, one per group, sorted by chromosome and position.
+    fn with_groups(panel: &MicrohapPanel, groups: Vec<Option<HashSet<String>>>) -> PanelTraversal {
+        let mut markers: Vec<(&String, &AlleleDefinition)> = panel.iter().collect();
+        markers.sort_by(|(id1, def1), (id2, def2)| {
+            (&def1.chromosome, def1.start(), id1).cmp(&(&def2.chromosome, def2.start(), id2))
+        });
+        let mut observers = Vec::with_capacity(markers.len() * groups.len());
+        for (mhid, definition) in markers {
+            for group in &groups {
+                let mut observer = HaplotypeObserver::new(definition);
+                observer.read_groups = group.clone();
+                observers.push((mhid.clone(), observer));
+            }
+        }
+        PanelTraversal {
+            observers,
+            groups: groups.len(),
+            missing_contigs: HashSet::new(),
+        }
+    }
+
+    /// Count observed haplotypes for all microhaps given the specified BAM or CRAM file, applying
+    /// the base quality, mapping quality, and depth limits in `parameters`. Clusters of overlapping
+    /// markers are visited in the coordinate order of the BAM header. With a single thread the file
+    /// is opened only once; otherwise clusters are distributed across a pool of workers, each with
+    /// its own BAM reader. Any threads not needed by the workers are used by htslib for BGZF
+    /// decompression. Markers on chromosomes absent from the alignment header are skipped, or
+    /// reported as an error if `parameters.strict_contigs` is set.
+    pub fn call_from_bam(
+        &mut self,
+        alignments: &AlignmentFile,
+        parameters: &TypingParameters,
+        threads: usize,
+    ) -> Result<()> {
+        let mut bam = alignments.open()?;
+        for (_, observer) in self.observers.iter_mut() {
+            observer.paired = parameters.paired;
+        }
+        let clusters = self.clusters();
+        let mut tasks: Vec<ClusterTask> = Vec::new();
+        let mut remaining = self.observers.as_mut_slice();
+        for cluster in clusters {
+            let (observers, rest) = remaining.split_at_mut(cluster.len());
+            remaining = rest;
+            let chrom = &observers[0].1.definition().chromosome;
+            match alignments.tid(bam.header(), chrom) {
+                Some(tid) => tasks.push((tid, observers)),
+                None => self
+                    .missing_contigs
+                    .extend(observers.iter().map(|(mhid, _)| mhid.clone())),
+            }
+        }
+        if parameters.strict_contigs && !self.missing_contigs.is_empty() {
+            let mut mhids: Vec<&str> = self.missing_contigs.iter().map(|s| s.as_str()).collect();
+            mhids.sort();
+            return Err(Error::Validation(format!(
+                "chromosome not found in alignment header {} for marker(s) {}",
+                alignments.path().display(),
+                mhids.join(", ")
+            )));
+        }
+        tasks.sort_by_key(|(tid, observers)| (*tid, observers[0].1.definition().start()));
+
+        let workers = threads.clamp(1, tasks.len().max(1));
+        let hts_threads = threads / workers;
+        if workers == 1 {
+            if hts_threads > 1 {
+                bam.set_threads(hts_threads)
+                    .map_err(|e| Error::bam(alignments.path(), e))?;
+            }
+            return Self::observe_clusters(&mut bam, alignments, tasks, parameters);
+        }
+        let mut assignments: Vec<Vec<ClusterTask>> = (0..workers).map(|_| Vec::new()).collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            assignments[i % workers].push(task);
+        }
+        thread::scope(|scope| {
+            let handles: Vec<_> = assignments
+                .into_iter()
+                .map(|assignment| {
+                    scope.spawn(move || {
+                        let mut bam = alignments.open()?;
+                        if hts_threads > 1 {
+                            bam.set_threads(hts_threads)
+                                .map_err(|e| Error::bam(alignments.path(), e))?;
+                        }
+                        Self::observe_clusters(&mut bam, alignments, assignment, parameters)
+                    })
+                })
+                .collect();
+            handles.into_iter().try_for_each(|handle| {
+                handle.join().unwrap_or_else(|panic| {
+                    let reason = match panic.downcast_ref::<&str>() {
+                        Some(message) => message.to_string(),
+                        None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+                    };
+                    let message = format!("traversal worker failed: {}", reason);
+                    Err(Error::bam(alignments.path(), message))
+                })
+            })
+        })
+    }
+
+    /// Fetch each cluster's extent from the BAM file and dispatch its pileups to the cluster's
+    /// observers.
+    fn observe_clusters(
+        bam: &mut bam::IndexedReader,
+        alignments: &AlignmentFile,
+        tasks: Vec<ClusterTask>,
+        parameters: &TypingParameters,
+    ) -> Result<()> {
+        for (tid, observers) in tasks {
+            let start = observers[0].1.definition().start();
+            let end = observers
+                .iter()
+                .map(|(_, observer)| observer.definition().end())
+                .max()
+                .unwrap_or(start);
+            bam.fetch((tid, start, end + 1))
+                .map_err(|e| Error::bam(alignments.path(), e))?;
+            let mut pileups = bam.pileup();
+            pileups.set_max_depth(parameters.max_depth);
+            let min_mapqs: Vec<u8> = observers
+                .iter()
+                .map(|(mhid, _)| parameters.min_mapping_quality.get(mhid))
+                .collect();
+            for pileup in pileups {
+                let pileup = pileup.map_err(|e| Error::bam(alignments.path(), e))?;
+                for ((_, observer), min_mapq) in observers.iter_mut().zip(&min_mapqs) {
+                    observer
+                        .observe_pileup(
+                            &pileup,
+                            parameters.min_base_quality,
+                            *min_mapq,
+                            parameters.max_depth,
+                        )
+                        .map_err(|e| {
+                            Error::bam(alignments.path(), format!("invalid read name: {}", e))
+                        })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Indicate whether the chromosome of the given marker was found in the alignment header.
+    pub fn contig_found(&self, mhid: &str) -> bool {
+        !self.missing_contigs.contains(mhid)
+    }
+
+    /// Iterate over microhap names/identifiers and corresponding observers, sorted by chromosome
+    /// and position. When split, each microhap is repeated once per group.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &HaplotypeObserver)> {
+        self.observers
+            .iter()
+            .map(|(mhid, observer)| (mhid, observer))
+    }
+
+    /// Iterate over microhap names/identifiers and the observers for the group with the given
+    /// index, sorted by chromosome and position.
+    pub fn group(&self, index: usize) -> impl Iterator<Item = (&String, &HaplotypeObserver)> {
+        self.iter().skip(index).step_by(self.groups.max(1))
+    }
+
+    /// Group sorted observers into runs of markers on the same chromosome with overlapping extents.
+    fn clusters(&self) -> Vec<Range<usize>> {
+        let mut clusters = Vec::new();
+        let mut cluster_start = 0;
+        let mut cluster_chrom = "";
+        let mut cluster_end = 0;
+        for (i, (_, observer)) in self.observers.iter().enumerate() {
+            let (chrom, start, end) = observer.definition().region();
+            if i > 0 && (chrom != cluster_chrom || start > cluster_end) {
+                clusters.push(cluster_start..i);
+                cluster_start = i;
+            }
+            if i == cluster_start {
+                cluster_chrom = chrom;
+                cluster_end = end;
+            }
+            cluster_end = cluster_end.max(end);
+        }
+        if !self.observers.is_empty() {
+            clusters.push(cluster_start..self.observers.len());
+        }
+        clusters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::ReadHaplotype;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_traversal_sorted() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv"))
+            .expect("issue parsing panel CSV");
+        let traversal = PanelTraversal::new(&panel);
+        let mhids: Vec<&String> = traversal.iter().map(|(mhid, _)| mhid).collect();
+        let expected = vec![
+            "mh17FHL-005.v3",
+            "mh03USC-3qC.v2",
+            "mh04WL-052.v1",
+            "mh06SCUZJ-0528857",
+        ];
+        assert_eq!(mhids, expected);
+    }
+
+    #[test]
+    fn test_traversal_matches_single_marker() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv"))
+            .expect("issue parsing panel CSV");
+        let bam_path = PathBuf::from("testdata/mwgfour-p2.bam");
+        let alignments = AlignmentFile::new(&bam_path, None);
+        let parameters = TypingParameters::defaults();
+        let mut traversal = PanelTraversal::new(&panel);
+        traversal
+            .call_from_bam(&alignments, &parameters, 1)
+            .unwrap();
+        for (mhid, observer) in traversal.iter() {
+            let mut single = HaplotypeObserver::new(panel.get(mhid).unwrap());
+            single.call_from_bam(&bam_path, 10, 0, 1e6 as u32);
+            assert_eq!(observer.count(), single.count());
+        }
+        let (_, observer) = traversal
+            .iter()
+            .find(|(mhid, _)| *mhid == "mh03USC-3qC.v2")
+            .unwrap();
+        let readhap = ReadHaplotype::from_string("CCACTGG");
+        assert!(observer.count().get(&readhap).is_some());
+    }
+
+    #[test]
+    fn test_traversal_split() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv"))
+            .expect("issue parsing panel CSV");
+        let alignments = AlignmentFile::new(&PathBuf::from("testdata/mwgfour-multiplex.bam"), None);
+        let parameters = TypingParameters::defaults();
+        let mut pooled = PanelTraversal::new(&panel);
+        pooled.call_from_bam(&alignments, &parameters, 1).unwrap();
+        let groups = vec![
+            HashSet::from(["lib1".to_string()]),
+            HashSet::from(["lib2a".to_string(), "lib2b".to_string()]),
+        ];
+        let mut split = PanelTraversal::split(&panel, &groups);
+        split.call_from_bam(&alignments, &parameters, 2).unwrap();
+        assert_eq!(split.iter().count(), 8);
+        for (((mhid, pooled), (mhid1, obs1)), (mhid2, obs2)) in
+            pooled.iter().zip(split.group(0)).zip(split.group(1))
+        {
+            assert_eq!(mhid, mhid1);
+            assert_eq!(mhid, mhid2);
+            let mut counts = obs1.count();
+            counts.extend(obs2.count());
+            assert_eq!(counts, pooled.count());
+            assert_ne!(obs1.count(), obs2.count());
+        }
+    }
+
+    #[test]
+    fn test_traversal_threads_deterministic() {
+        let panel = MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv"))
+            .expect("issue parsing panel CSV");
+        let alignments = AlignmentFile::new(&PathBuf::from("testdata/mwgfour-p1p3.bam"), None);
+        let parameters = TypingParameters::defaults();
+        let mut serial = PanelTraversal::new(&panel);
+        serial.call_from_bam(&alignments, &parameters, 1).unwrap();
+        for threads in [2, 3, 8] {
+            let mut parallel = PanelTraversal::new(&panel);
+            parallel
+                .call_from_bam(&alignments, &parameters, threads)
+                .unwrap();
+            for ((mhid1, obs1), (mhid2, obs2)) in serial.iter().zip(parallel.iter()) {
+                assert_eq!(mhid1, mhid2);
+                assert_eq!(obs1.count(), obs2.count());
+                assert_eq!(obs1.discarded(), obs2.discarded());
+                assert_eq!(obs1.coverage(), obs2.coverage());
+            }
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// -------------------------------------------------------------------------------------------------
+// Copyright (c) 2025, DHS.
+// This file is part of mhrs: https://github.com/bioforensics/mhrs/
+//
+// This software was prepared for the Department of Homeland Security (DHS) by the Battelle National
+// Biodefense Institute, LLC (BNBI) as part of contract HSHQDC-15-C-00064 to manage and operate the
+// National Biodefense Analysis and Countermeasures Center (NBACC), a Federally Funded Research and
+// Development Center.
+// -------------------------------------------------------------------------------------------------
+
+use crate::definition::AlleleDefinition;
+use crate::error::{Error, Result};
+use crate::panel::MicrohapPanel;
+use crate::profile::MicrohapProfile;
+use crate::read::ReadHaplotype;
+use crate::result::{MarkerStatus, TypingResult};
+use std::collections::{BTreeSet, HashSet};
+use std::io::Write;
+
+/// Data structure for writing the genotype calls of one or more microhap profiles in VCF format,
+/// with one sample column per profile.
+///
+/// Each marker is reported as a single record positioned at its first ADS, with the last ADS given
+/// by `END` and every ADS given by `ADS` in the INFO column (both 1-based). Since a microhap allele
+/// is a combination of non-adjacent SNPs rather than a contiguous sequence, each haplotype called
+/// in any sample is reported as a symbolic ALT allele such as `<CCACTGG>`, listing the base at each
+/// ADS in order. If the profiles identify the reference haplotype, it is given by `REFHAP` in the
+/// INFO column and serves as the REF allele, represented by the reference base at the first ADS;
+/// otherwise the REF allele is `N`. For each sample, `GT` lists the called haplotypes (twice for a
+/// homozygote, more than two for a mixture, or `./.` if none), `AD` gives the read count of each
+/// allele, and `DP` the mean read depth across the marker.
+pub struct VcfWriter {
+    panel: MicrohapPanel,
+    profiles: Vec<MicrohapProfile>,
+}
+
+impl VcfWriter {
+    /// Prepare the given profiles for output, in order, using the allele definitions to position
+    /// each marker. Fails if two profiles have the same sample ID or if a profile includes a marker
+    /// absent from the allele definitions.
+    pub fn new(panel: MicrohapPanel, profiles: Vec<MicrohapProfile>) -> Result<VcfWriter> {
+        let mut samples = HashSet::new();
+        for profile in &profiles {
+            if !samples.insert(profile.sample_id()) {
+                let message = format!("duplicate sample ID {} in profiles", profile.sample_id());
+                return Err(Error::Validation(message));
+            }
+            if let Some((mhid, _)) = profile.iter().find(|(mhid, _)| panel.get(mhid).is_none()) {
+                return Err(Error::Validation(format!(
+                    "marker {} in profile {} absent from allele definitions",
+                    mhid,
+                    profile.sample_id()
+                )));
+            }
+        }
+        Ok(VcfWriter { panel, profiles })
+    }
+
+    /// Write the VCF header and one record per marker typed in any profile, sorted by chromosome
+    /// and position.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        let markers = self.markers();
+        writeln!(writer, "##fileformat=VCFv4.3")?;
+        writeln!(writer, "##source=mhrs {}", env!("CARGO_PKG_VERSION"))?;
+        let chromosomes: BTreeSet<&str> = markers
+            .iter()
+            .map(|(_, definition)| definition.chromosome.as_str())
+            .collect();
+        for chrom in chromosomes {
+            writeln!(writer, "##contig=<ID={}>", chrom)?;
+        }
+        for line in HEADER {
+            writeln!(writer, "{}", line)?;
+        }
+        let samples: Vec<&str> = self.profiles.iter().map(|p| p.sample_id()).collect();
+        writeln!(
+            writer,
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}",
+            samples.join("\t")
+        )?;
+        for (mhid, definition) in markers {
+            writeln!(writer, "{}", self.record(mhid, definition))?;
+        }
+        Ok(())
+    }
+
+    /// Markers typed in any profile, with their definitions, sorted by chromosome and position.
+    fn markers(&self) -> Vec<(&String, &AlleleDefinition)> {
+        let mut markers: Vec<(&String, &AlleleDefinition)> = self
+            .panel
+            .iter()
+            .filter(|(mhid, _)| self.profiles.iter().any(|p| p.get(mhid).is_some()))
+            .collect();
+        markers.sort_by(|(id1, def1), (id2, def2)| {
+            (&def1.chromosome, def1.start(), id1).cmp(&(&def2.chromosome, def2.start(), id2))
+        });
+        markers
+    }
+
+    /// Tab-separated VCF record for the given marker.
+    fn record(&self, mhid: &str, definition: &AlleleDefinition) -> String {
+        let results: Vec<Option<&TypingResult>> =
+            self.profiles.iter().map(|p| p.get(mhid)).collect();
+        let reference = results
+            .iter()
+            .flatten()
+            .find_map(|result| result.reference.as_ref());
+        let called: BTreeSet<&ReadHaplotype> = results
+            .iter()
+            .flatten()
+            .flat_map(|result| result.genotype.iter())
+            .filter(|allele| Some(*allele) != reference)
+            .collect();
+        let alt = match called.is_empty() {
+            true => ".".to_string(),
+            false => called
+                .iter()
+                .map(|allele| format!("<{}>", allele))
+                .collect::<Vec<String>>()
+                .join(","),
+        };
+        let mut alleles = vec![reference];
+        alleles.extend(called.into_iter().map(Some));
+        let offsets: Vec<String> = definition
+            .get_offsets()
+            .iter()
+            .map(|offset| (offset + 1).to_string())
+            .collect();
+        let mut info = format!("END={};ADS={}", definition.end() + 1, offsets.join(","));
+        let ref_base = match reference {
+            Some(reference) => {
+                info.push_str(&format!(";REFHAP={}", reference));
+                reference.get(0).unwrap_or('N')
+            }
+            None => 'N',
+        };
+        let mut fields = vec![
+            definition.chromosome.clone(),
+            (definition.start() + 1).to_string(),
+            mhid.to_string(),
+            ref_base.to_string(),
+            alt,
+            ".".to_string(),
+            ".".to_string(),
+            info,
+            "GT:AD:DP".to_string(),
+        ];
+        fields.extend(results.iter().map(|result| sample_field(*result, &alleles)));
+        fields.join("\t")
+    }
+}
+
+/// INFO and FORMAT header lines.
+const HEADER: [&str; 6] = [
+    "##INFO=<ID=END,Number=1,Type=Integer,Description=\"Position of the last ADS\">",
+    "##INFO=<ID=ADS,Number=.,Type=Integer,Description=\"Position of each ADS\">",
+    "##INFO=<ID=REFHAP,Number=1,Type=String,Description=\"Reference haplotype\">",
+    "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+    "##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Read count of each allele\">",
+    "##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Mean read depth across the marker\">",
+];
+
+/// GT:AD:DP field of a single sample, given the alleles of the record: the reference haplotype, if
+/// known, followed by the ALT alleles.
+fn sample_field(result: Option<&TypingResult>, alleles: &[Option<&ReadHaplotype>]) -> String {
+    let result = match result {
+        Some(result) if result.status != MarkerStatus::ContigNotFound => result,
+        _ => return "./.:.:.".to_string(),
+    };
+    let mut indices: Vec<usize> = result
+        .genotype
+        .iter()
+        .map(|allele| alleles.iter().position(|a| *a == Some(allele)).unwrap())
+        .collect();
+    indices.sort();
+    let mut indices: Vec<String> = indices.iter().map(|index| index.to_string()).collect();
+    match indices.len() {
+        0 => indices = vec![".".to_string(), ".".to_string()],
+        1 => indices.push(indices[0].clone()),
+        _ => (),
+    }
+    let genotype = indices.join("/");
+    let depths: Vec<String> = alleles
+        .iter()
+        .map(|allele| match allele {
+            Some(allele) => result.counts.tally.get(*allele).unwrap_or(&0).to_string(),
+            None => "0".to_string(),
+        })
+        .collect();
+    let depth = result.coverage.mean.round() as u32;
+    format!("{}:{}:{}", genotype, depths.join(","), depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn load_profile(path: &str) -> MicrohapProfile {
+        MicrohapProfile::from_path(Path::new(path)).unwrap()
+    }
+
+    fn load_panel() -> MicrohapPanel {
+        MicrohapPanel::from_csv(&PathBuf::from("testdata/mwgfour.csv")).unwrap()
+    }
+
+    #[test]
+    fn test_vcf_multi_sample() {
+        let profiles = vec![
+            load_profile("testdata/mwgfour-p1-profile.json"),
+            load_profile("testdata/mwgfour-p2-profile.json"),
+        ];
+        let writer = VcfWriter::new(load_panel(), profiles).unwrap();
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+        let vcf = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = vcf.lines().collect();
+        assert_eq!(lines[0], "##fileformat=VCFv4.3");
+        assert!(lines.contains(&"##contig=<ID=chr17>"));
+        let header = lines.iter().position(|l| l.starts_with("#CHROM")).unwrap();
+        assert!(lines[header].ends_with("FORMAT\tItem1\tItem2"));
+        let records = &lines[header + 1..];
+        assert_eq!(records.len(), 4);
+        let chroms: Vec<&str> = records
+            .iter()
+            .map(|r| r.split('\t').next().unwrap())
+            .collect();
+        assert_eq!(chroms, vec!["chr17", "chr3", "chr4", "chr6"]);
+        let fields: Vec<&str> = records[1].split('\t').collect();
+        assert_eq!(fields[..4], ["chr3", "196652866", "mh03USC-3qC.v2", "N"]);
+        assert_eq!(fields[4], "<CCACTGG>,<CCACTGT>,<CTACTGG>");
+        assert!(fields[7].starts_with("END=196653122;ADS=196652866,196652972,"));
+        assert_eq!(fields[9], "2/2:0,2,2322,0:2469");
+        assert_eq!(fields[10], "1/3:0,1151,2,1151:2460");
+    }
+
+    #[test]
+    fn test_vcf_reference_haplotype() {
+        let mut profile = MicrohapProfile::new("Item2");
+        let mut result = TypingResult::from_file("testdata/dummy-result.json");
+        result.reference = Some(ReadHaplotype::from_string("GCTTCT"));
+        profile.add("mh17FHL-005.v3", result);
+        let writer = VcfWriter::new(load_panel(), vec![profile]).unwrap();
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+        let vcf = String::from_utf8(output).unwrap();
+        let record = vcf.lines().last().unwrap();
+        let fields: Vec<&str> = record.split('\t').collect();
+        assert_eq!(fields[3..5], ["G", "<AGTTTT>"]);
+        assert!(fields[7].ends_with(";REFHAP=GCTTCT"));
+        assert_eq!(fields[9], "0/1:1184,1183:2478");
+    }
+
+    #[test]
+    fn test_vcf_split_profiles() {
+        let path = Path::new("testdata/mwgfour-multiplex-profiles.json");
+        let profiles = MicrohapProfile::all_from_path(path).unwrap();
+        let writer = VcfWriter::new(load_panel(), profiles).unwrap();
+        let mut output = Vec::new();
+        writer.write(&mut output).unwrap();
+        let vcf = String::from_utf8(output).unwrap();
+        let header = vcf.lines().find(|l| l.starts_with("#CHROM")).unwrap();
+        assert!(header.ends_with("FORMAT\tItem1\tItem2"));
+        let record = vcf.lines().find(|l| l.contains("mh03USC-3qC.v2")).unwrap();
+        let fields: Vec<&str> = record.split('\t').collect();
+        assert_eq!(fields.len(), 11);
+        assert_ne!(fields[9], "./.:.:.");
+        assert_ne!(fields[9], fields[10]);
+    }
+
+    #[test]
+    fn test_vcf_duplicate_samples() {
+        let profiles = vec![
+            load_profile("testdata/mwgfour-p2-profile.json"),
+            load_profile("testdata/mwgfour-p2-profile.json"),
+        ];
+        let message = VcfWriter::new(load_panel(), profiles)
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(message, "duplicate sample ID Item2 in profiles");
+    }
+}